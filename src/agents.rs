@@ -0,0 +1,114 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use lazy_static::lazy_static;
+use warp::{Filter, Rejection};
+
+use crate::{
+    config::{AgentConfig, CONFIG},
+    endpoints::find_operation,
+    manifest::{render_manifest, Operation},
+};
+
+lazy_static! {
+    /// Agents from the config by host prefix
+    pub static ref AGENTS: HashMap<String, Agent> =
+        load_agents().expect("Failed to load agents");
+}
+
+pub struct Agent {
+    pub manifest: String,
+    routes: HashSet<&'static str>,
+}
+
+impl Agent {
+    fn from_config(config: &AgentConfig) -> Result<Self, anyhow::Error> {
+        let operations = config
+            .routes
+            .iter()
+            .map(|route| {
+                find_operation(route)
+                    .copied()
+                    .with_context(|| format!("Agent {}: unknown route {route}", config.host_prefix))
+            })
+            .collect::<Result<Vec<Operation>, _>>()?;
+        if let Some(route) = config
+            .route_descriptions
+            .keys()
+            .find(|route| !config.routes.contains(route))
+        {
+            anyhow::bail!(
+                "Agent {}: description for route {route} that the agent doesn't expose",
+                config.host_prefix
+            );
+        }
+        Ok(Self {
+            manifest: render_manifest(config, &operations).to_string(),
+            routes: operations.iter().map(|operation| operation.path).collect(),
+        })
+    }
+
+    pub fn exposes(&self, path: &str) -> bool {
+        self.routes.contains(path)
+    }
+}
+
+fn load_agents() -> Result<HashMap<String, Agent>, anyhow::Error> {
+    let mut agents = HashMap::new();
+    for config in &CONFIG.agents {
+        let agent = Agent::from_config(config)?;
+        if agents.insert(config.host_prefix.clone(), agent).is_some() {
+            anyhow::bail!("Duplicate agent {}", config.host_prefix);
+        }
+    }
+    if let Some(default_agent) = default_agent_name() {
+        if !agents.contains_key(default_agent) {
+            anyhow::bail!("Default agent {default_agent} is not configured");
+        }
+    }
+    Ok(agents)
+}
+
+fn default_agent_name() -> Option<&'static str> {
+    #[cfg(feature = "local-debug-agent")]
+    {
+        Some(
+            CONFIG
+                .default_agent
+                .as_deref()
+                .unwrap_or(concat!(env!("DEBUG_AGENT"), "-agent")),
+        )
+    }
+    #[cfg(not(feature = "local-debug-agent"))]
+    {
+        CONFIG.default_agent.as_deref()
+    }
+}
+
+/// The agent is chosen by the first label of the Host header, e.g.
+/// `staking-agent.intear.tech` is `staking-agent`
+pub fn find_agent(host: &str) -> Option<&'static Agent> {
+    let host_prefix = host.split('.').next().unwrap_or_default();
+    AGENTS
+        .get(host_prefix)
+        .or_else(|| AGENTS.get(default_agent_name()?))
+}
+
+/// Extracts the agent of the request's Host, if any
+pub fn agent() -> impl Filter<Extract = (Option<&'static Agent>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("host")
+        .map(|host: Option<String>| host.as_deref().and_then(find_agent))
+}
+
+/// Matches `/{operation.path}` only on hosts of agents that expose it
+pub fn route(operation: Operation) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path(operation.path)
+        .and(agent())
+        .and_then(move |agent: Option<&'static Agent>| async move {
+            match agent {
+                Some(agent) if agent.exposes(operation.path) => Ok(()),
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+}
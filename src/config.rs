@@ -0,0 +1,299 @@
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::Context;
+use bigdecimal::BigDecimal;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::utils::amount::NearAmount;
+
+/// Path of the config file, can be overridden with `TOKEN_AGENT_CONFIG`
+const DEFAULT_CONFIG_PATH: &str = "token-agent.toml";
+const ENV_PREFIX: &str = "TOKEN_AGENT_";
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config::load().expect("Failed to load config");
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: SocketAddr,
+    /// Tried in order, the next one is used if the previous one fails
+    pub rpc_urls: Vec<String>,
+    pub fastnear_api_url: String,
+    pub prices_api_url: String,
+    /// Price service with the `/price-history` endpoint, see
+    /// `fetch_price_history`. `None` to only use prices recorded by this
+    /// server.
+    pub price_history_api_url: Option<String>,
+    pub token_refresh_interval_secs: u64,
+    /// Used instead of `token_refresh_interval_secs` while the token list
+    /// has never been loaded
+    pub token_retry_interval_secs: u64,
+    /// NEAR left on the account for gas when staking, wrapping, or swapping
+    /// NEAR, in NEAR like `0.05`
+    pub gas_reserve_near: BigDecimal,
+    pub cache: CacheConfig,
+    pub snapshot: SnapshotConfig,
+    /// Replaces the default agents if set
+    pub agents: Vec<AgentConfig>,
+    /// Host prefix of the agent used for hosts that don't match any agent
+    pub default_agent: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// TTL of `view_account` and `call_function` results
+    pub rpc_ttl_secs: u64,
+    /// TTL of FastNear and prices.intear.tech responses
+    pub http_ttl_secs: u64,
+    /// TTL of `ft_metadata` of tokens that aren't in the token list, and of
+    /// contracts that turned out not to be tokens
+    pub ft_metadata_ttl_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Where the token list is saved after each refresh, `None` to disable
+    pub path: Option<PathBuf>,
+    /// Snapshots older than this are ignored on startup
+    pub max_age_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentConfig {
+    /// First label of the Host header, e.g. `tokens-agent` for
+    /// `tokens-agent.intear.tech`
+    pub host_prefix: String,
+    pub server_url: String,
+    pub account_id: String,
+    pub title: String,
+    pub description: String,
+    pub assistant_name: String,
+    pub assistant_description: String,
+    pub instructions: String,
+    /// Bitte tools the assistant can use, e.g. `generate-transaction`
+    #[serde(default)]
+    pub tools: Vec<String>,
+    pub image: Option<String>,
+    /// Paths of the endpoints this agent exposes, without the leading slash
+    pub routes: Vec<String>,
+    /// Replaces the default description of an endpoint in the manifest, by
+    /// route
+    #[serde(default)]
+    pub route_descriptions: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_address: if cfg!(debug_assertions) {
+                ([127, 0, 0, 1], 3030).into()
+            } else {
+                ([0, 0, 0, 0], 80).into()
+            },
+            rpc_urls: vec![
+                "https://rpc.intear.tech".to_string(),
+                "https://rpc.shitzuapes.xyz".to_string(),
+                "https://rpc.mainnet.near.org".to_string(),
+                "https://near.lava.build".to_string(),
+            ],
+            fastnear_api_url: "https://api.fastnear.com".to_string(),
+            prices_api_url: "https://prices.intear.tech".to_string(),
+            price_history_api_url: None,
+            token_refresh_interval_secs: 30,
+            token_retry_interval_secs: 5,
+            gas_reserve_near: BigDecimal::new(5.into(), 2),
+            cache: CacheConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            agents: default_agents(),
+            default_agent: None,
+        }
+    }
+}
+
+fn default_agents() -> Vec<AgentConfig> {
+    vec![
+        AgentConfig {
+            host_prefix: "tokens-agent".to_string(),
+            server_url: "https://tokens-agent.intear.tech".to_string(),
+            account_id: "price.agent.intear.near".to_string(),
+            title: "Tokens & Prices".to_string(),
+            description: "Can get token prices and user balances.".to_string(),
+            assistant_name: "Tokens & Prices".to_string(),
+            assistant_description: "Get balances and tokens".to_string(),
+            instructions:
+                "You are an assistant that help users get their token prices and balances."
+                    .to_string(),
+            tools: vec!["generate-transaction".to_string()],
+            image: None,
+            routes: vec![
+                "token-prices".to_string(),
+                "token-history".to_string(),
+                "token-info".to_string(),
+                "total-balance".to_string(),
+                "staking".to_string(),
+                "transfer".to_string(),
+                "wrap".to_string(),
+                "unwrap".to_string(),
+                "swap-quote".to_string(),
+                "swap".to_string(),
+            ],
+            route_descriptions: HashMap::from([(
+                "staking".to_string(),
+                "Returns the total NEAR balance of an account, staked and unstaked NEAR balance."
+                    .to_string(),
+            )]),
+        },
+        AgentConfig {
+            host_prefix: "staking-agent".to_string(),
+            server_url: "https://staking-agent.intear.tech".to_string(),
+            account_id: "stake.agent.intear.near".to_string(),
+            title: "Staking".to_string(),
+            description: "Stake, unstake, and see staking information".to_string(),
+            assistant_name: "Staking".to_string(),
+            assistant_description: "Stake, unstake, and see staking information".to_string(),
+            instructions:
+                "You are an assistant that help users stake, unstake, and see staking information."
+                    .to_string(),
+            tools: vec!["generate-transaction".to_string()],
+            image: Some("https://www.bitte.ai/agents/stake-logo.jpg".to_string()),
+            routes: vec![
+                "staking".to_string(),
+                "stake".to_string(),
+                "unstake".to_string(),
+                "validators".to_string(),
+                "resolve-pool".to_string(),
+            ],
+            route_descriptions: HashMap::new(),
+        },
+    ]
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            rpc_ttl_secs: 30,
+            http_ttl_secs: 30,
+            ft_metadata_ttl_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            path: Some(PathBuf::from("tokens-snapshot.json")),
+            max_age_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file if it exists, then applies `TOKEN_AGENT_*`
+    /// environment variable overrides
+    fn load() -> Result<Self, anyhow::Error> {
+        let (path, required) = match std::env::var(format!("{ENV_PREFIX}CONFIG")) {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                Config::default()
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+
+        override_from_env(&mut config.bind_address, "BIND_ADDRESS")?;
+        if let Some(rpc_urls) = env_var("RPC_URLS") {
+            config.rpc_urls = rpc_urls
+                .split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect();
+        }
+        override_from_env(&mut config.fastnear_api_url, "FASTNEAR_API_URL")?;
+        override_from_env(&mut config.prices_api_url, "PRICES_API_URL")?;
+        if let Some(url) = env_var("PRICE_HISTORY_API_URL") {
+            config.price_history_api_url = (!url.is_empty()).then_some(url);
+        }
+        override_from_env(
+            &mut config.token_refresh_interval_secs,
+            "TOKEN_REFRESH_INTERVAL_SECS",
+        )?;
+        override_from_env(
+            &mut config.token_retry_interval_secs,
+            "TOKEN_RETRY_INTERVAL_SECS",
+        )?;
+        override_from_env(&mut config.gas_reserve_near, "GAS_RESERVE_NEAR")?;
+        override_from_env(&mut config.cache.rpc_ttl_secs, "CACHE_RPC_TTL_SECS")?;
+        override_from_env(&mut config.cache.http_ttl_secs, "CACHE_HTTP_TTL_SECS")?;
+        override_from_env(
+            &mut config.cache.ft_metadata_ttl_secs,
+            "CACHE_FT_METADATA_TTL_SECS",
+        )?;
+        if let Some(path) = env_var("SNAPSHOT_PATH") {
+            config.snapshot.path = (!path.is_empty()).then(|| PathBuf::from(path));
+        }
+        override_from_env(&mut config.snapshot.max_age_secs, "SNAPSHOT_MAX_AGE_SECS")?;
+        if let Some(default_agent) = env_var("DEFAULT_AGENT") {
+            config.default_agent = (!default_agent.is_empty()).then_some(default_agent);
+        }
+
+        if config.rpc_urls.is_empty() {
+            anyhow::bail!("At least one RPC URL is required");
+        }
+        if NearAmount::from_near(&config.gas_reserve_near).is_none() {
+            anyhow::bail!("gas_reserve_near must be a valid NEAR amount");
+        }
+        config.fastnear_api_url = config.fastnear_api_url.trim_end_matches('/').to_string();
+        config.prices_api_url = config.prices_api_url.trim_end_matches('/').to_string();
+        config.price_history_api_url = config
+            .price_history_api_url
+            .map(|url| url.trim_end_matches('/').to_string());
+        Ok(config)
+    }
+
+    pub fn token_refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.token_refresh_interval_secs)
+    }
+
+    pub fn token_retry_interval(&self) -> Duration {
+        Duration::from_secs(self.token_retry_interval_secs)
+    }
+
+    pub fn gas_reserve(&self) -> NearAmount {
+        NearAmount::from_near(&self.gas_reserve_near)
+            .expect("gas_reserve_near is validated on load")
+    }
+}
+
+impl SnapshotConfig {
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs)
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(format!("{ENV_PREFIX}{name}")).ok()
+}
+
+fn override_from_env<T: FromStr>(value: &mut T, name: &str) -> Result<(), anyhow::Error>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Some(env_value) = env_var(name) {
+        *value = env_value
+            .parse()
+            .map_err(|err| anyhow::anyhow!("Invalid {ENV_PREFIX}{name}: {err}"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use warp::http::StatusCode;
+
+use crate::{config::CONFIG, global_state::TOKENS, utils::rpc::get_reqwest_client};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+pub async fn get_healthz() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&Health { status: "ok" }))
+}
+
+#[derive(Debug, Serialize)]
+struct Version {
+    version: &'static str,
+    git_hash: &'static str,
+    features: Vec<&'static str>,
+}
+
+pub async fn get_version() -> Result<impl warp::Reply, warp::Rejection> {
+    let mut features = Vec::new();
+    if cfg!(feature = "local-debug-agent") {
+        features.push("local-debug-agent");
+    }
+    Ok(warp::reply::json(&Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        features,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    tokens: TokensStatus,
+    rpcs: Vec<DependencyStatus>,
+    fastnear: DependencyStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct TokensStatus {
+    ready: bool,
+    token_count: usize,
+    /// Unix timestamp of the last successful refresh
+    last_refreshed_at: Option<u64>,
+    age_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct DependencyStatus {
+    url: String,
+    ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    latency_ms: u128,
+}
+
+pub async fn get_readyz() -> Result<impl warp::Reply, warp::Rejection> {
+    let tokens = {
+        let tokens = TOKENS.read().await;
+        TokensStatus {
+            ready: tokens.updated_at.is_some(),
+            token_count: tokens.tokens.len(),
+            last_refreshed_at: tokens.updated_at.map(|updated_at| {
+                updated_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            }),
+            age_secs: tokens
+                .updated_at
+                .map(|updated_at| updated_at.elapsed().unwrap_or_default().as_secs()),
+        }
+    };
+    let (rpcs, fastnear) = tokio::join!(
+        futures_util::future::join_all(CONFIG.rpc_urls.iter().map(|url| check_rpc(url))),
+        check_fastnear(),
+    );
+
+    let readiness = Readiness {
+        ready: tokens.ready && rpcs.iter().any(|rpc| rpc.ready) && fastnear.ready,
+        tokens,
+        rpcs,
+        fastnear,
+    };
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&readiness),
+        status,
+    ))
+}
+
+async fn check_rpc(url: &str) -> DependencyStatus {
+    check(url, async {
+        let response: serde_json::Value = get_reqwest_client()
+            .post(url)
+            .json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": "dontcare",
+                "method": "status",
+                "params": [],
+            }))
+            .timeout(CHECK_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if response.get("result").is_none() {
+            anyhow::bail!("No result in response: {response}");
+        }
+        Ok(())
+    })
+    .await
+}
+
+async fn check_fastnear() -> DependencyStatus {
+    let url = format!("{}/status", CONFIG.fastnear_api_url);
+    check(&url, async {
+        get_reqwest_client()
+            .get(&url)
+            .timeout(CHECK_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    })
+    .await
+}
+
+async fn check(
+    url: &str,
+    request: impl std::future::Future<Output = Result<(), anyhow::Error>>,
+) -> DependencyStatus {
+    let start = std::time::Instant::now();
+    let result = request.await;
+    if let Err(err) = &result {
+        log::warn!("Readiness check of {url} failed: {err:?}");
+    }
+    DependencyStatus {
+        url: url.to_string(),
+        ready: result.is_ok(),
+        error: result.err().map(|err| err.to_string()),
+        latency_ms: start.elapsed().as_millis(),
+    }
+}
@@ -0,0 +1,10 @@
+use crate::{error::AgentError, utils::metrics::gather};
+
+pub async fn get_metrics() -> Result<impl warp::Reply, warp::Rejection> {
+    let metrics = gather().await.map_err(AgentError::Internal)?;
+    Ok(warp::reply::with_header(
+        metrics,
+        "content-type",
+        prometheus::TEXT_FORMAT,
+    ))
+}
@@ -1,5 +1,40 @@
+pub mod health;
+pub mod metrics;
+pub mod resolve_pool;
 pub mod stake;
 pub mod staking;
+pub mod swap;
+pub mod swap_quote;
+pub mod token_history;
+pub mod token_info;
 pub mod token_prices;
 pub mod total_balance;
+pub mod transfer;
 pub mod unstake;
+pub mod unwrap;
+pub mod validators;
+pub mod wrap;
+
+use crate::manifest::Operation;
+
+/// Endpoints that agents can expose, see `AgentConfig::routes`
+pub const OPERATIONS: &[Operation] = &[
+    total_balance::OPERATION,
+    token_prices::OPERATION,
+    token_history::OPERATION,
+    token_info::OPERATION,
+    staking::OPERATION,
+    resolve_pool::OPERATION,
+    stake::OPERATION,
+    unstake::OPERATION,
+    validators::OPERATION,
+    transfer::OPERATION,
+    wrap::OPERATION,
+    unwrap::OPERATION,
+    swap_quote::OPERATION,
+    swap::OPERATION,
+];
+
+pub fn find_operation(path: &str) -> Option<&'static Operation> {
+    OPERATIONS.iter().find(|operation| operation.path == path)
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Reply;
+
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{response::ResponseFormat, validators::get_epoch_validators},
+};
+
+use super::staking::{get_delegated_validators, NOT_STAKING_THRESHOLD};
+
+/// At most this many candidates are returned
+const MAX_CANDIDATES: usize = 5;
+/// Candidates below this confidence are not returned
+const MIN_CONFIDENCE: f64 = 0.4;
+/// The best candidate is used without asking the user if it's at least this
+/// confident and the second best is `MIN_CONFIDENCE_GAP` less confident
+const AUTO_RESOLVE_CONFIDENCE: f64 = 0.9;
+const MIN_CONFIDENCE_GAP: f64 = 0.1;
+/// Added to the confidence of pools the user already delegates to
+const DELEGATION_BONUS: f64 = 0.1;
+/// Longer queries are rejected before they're compared to every pool name,
+/// account IDs are at most 64 characters
+const MAX_QUERY_LENGTH: usize = 64;
+/// Words that users add to pool names, like "shitzu pool"
+const FILLER_WORDS: &[&str] = &["pool", "poolv1", "validator", "staking", "near", "the"];
+
+pub const OPERATION: Operation = Operation {
+    path: "resolve-pool",
+    operation_id: "resolvePool",
+    summary: "Find the account ID of a staking pool by its name",
+    description: "Returns staking pools matching a name like `intear` or `shitzu pool`, from active validators and pools the user is staking in, with how confident the match is. Use it when the user names a pool without its full account ID, and ask the user to choose if there are several likely candidates.",
+    response_description: "Candidate pool account IDs, the most likely first.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<ResolvePoolInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ResolvePoolInput {
+    /// The pool name or part of it, as the user said it.
+    pub query: String,
+    /// The account ID of the user, to prefer pools they're already staking in.
+    #[schemars(with = "Option<String>")]
+    pub account_id: Option<AccountId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolCandidate {
+    pub pool_id: AccountId,
+    /// From 0 to 1
+    pub confidence: f64,
+    /// Validating now or in the next epoch
+    pub is_validator: bool,
+    /// The user has NEAR staked or unstaked in this pool
+    pub is_delegated: bool,
+}
+
+/// JSON response of `/resolve-pool`
+#[derive(Debug, Serialize)]
+pub struct ResolvePoolResponse {
+    pub query: String,
+    /// Set if the best candidate is confident enough to use without asking
+    pub resolved: Option<AccountId>,
+    pub candidates: Vec<PoolCandidate>,
+}
+
+/// Result of resolving a pool name
+pub enum ResolvedPool {
+    Pool(AccountId),
+    /// Nothing matches confidently, the message lists candidates for the LLM
+    Ambiguous(String),
+}
+
+pub async fn get_resolve_pool(
+    input: ResolvePoolInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let ResolvePoolInput { query, account_id } = input;
+    let candidates = find_pools(&query, account_id.as_ref()).await?;
+    let resolved = confident_candidate(&candidates).map(|candidate| candidate.pool_id.clone());
+    Ok(match format {
+        ResponseFormat::Json => warp::reply::json(&ResolvePoolResponse {
+            query,
+            resolved,
+            candidates,
+        })
+        .into_response(),
+        ResponseFormat::Text => match resolved {
+            Some(pool_id) => format!(
+                "`{query}` is most likely {pool_id}\n{}",
+                describe_candidates(&candidates)
+            ),
+            None => ambiguous_message(&query, &candidates),
+        }
+        .into_response(),
+    })
+}
+
+/// Pool account ID for `/stake` and `/unstake`. Full account IDs are used as
+/// is, other names are resolved if there's a confident match.
+pub async fn resolve_pool(
+    query: &str,
+    account_id: Option<&AccountId>,
+) -> Result<ResolvedPool, AgentError> {
+    let query = query.trim();
+    if query.contains('.') {
+        if let Ok(pool_id) = query.to_lowercase().parse::<AccountId>() {
+            return Ok(ResolvedPool::Pool(pool_id));
+        }
+    }
+    let candidates = find_pools(query, account_id).await?;
+    if candidates.is_empty() && query.contains('.') {
+        // Looks like an account ID, but isn't a valid one
+        return Err(AgentError::InvalidAccount(query.to_string()));
+    }
+    Ok(match confident_candidate(&candidates) {
+        Some(candidate) => ResolvedPool::Pool(candidate.pool_id.clone()),
+        None => ResolvedPool::Ambiguous(ambiguous_message(query, &candidates)),
+    })
+}
+
+/// Matching pools from the validator set and the user's delegations, the
+/// most confident first
+pub async fn find_pools(
+    query: &str,
+    account_id: Option<&AccountId>,
+) -> Result<Vec<PoolCandidate>, AgentError> {
+    if query.len() > MAX_QUERY_LENGTH {
+        return Err(AgentError::InvalidInput(format!(
+            "The pool name is too long, it can be at most {MAX_QUERY_LENGTH} characters"
+        )));
+    }
+    let mut pools: HashMap<AccountId, (bool, bool)> = HashMap::new();
+    let validators = get_epoch_validators().await;
+    if let Ok(epoch) = &validators {
+        for pool_id in epoch.validator_ids() {
+            pools.entry(pool_id.clone()).or_default().0 = true;
+        }
+    }
+    if let Some(account_id) = account_id {
+        match get_delegated_validators(account_id).await {
+            Ok(staking_data) => {
+                for data in staking_data {
+                    if data.staked_amount > NOT_STAKING_THRESHOLD
+                        || data.unstaked_amount > NOT_STAKING_THRESHOLD
+                    {
+                        pools.entry(data.pool_id).or_default().1 = true;
+                    }
+                }
+            }
+            Err(err) => log::warn!("Failed to get pools {account_id} is staking in: {err:?}"),
+        }
+    }
+    if pools.is_empty() {
+        if let Err(err) = validators {
+            return Err(AgentError::rpc(err));
+        }
+    }
+
+    let query_name = normalize_name(query);
+    let mut candidates = pools
+        .into_iter()
+        .filter_map(|(pool_id, (is_validator, is_delegated))| {
+            let mut confidence = match_confidence(query, &query_name, &pool_id);
+            if confidence < MIN_CONFIDENCE {
+                return None;
+            }
+            if is_delegated {
+                confidence = (confidence + DELEGATION_BONUS).min(1.0);
+            }
+            Some(PoolCandidate {
+                pool_id,
+                confidence: (confidence * 100.0).round() / 100.0,
+                is_validator,
+                is_delegated,
+            })
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| {
+        b.confidence
+            .total_cmp(&a.confidence)
+            .then_with(|| a.pool_id.cmp(&b.pool_id))
+    });
+    candidates.truncate(MAX_CANDIDATES);
+    Ok(candidates)
+}
+
+fn confident_candidate(candidates: &[PoolCandidate]) -> Option<&PoolCandidate> {
+    let best = candidates.first()?;
+    let second = candidates.get(1).map_or(0.0, |second| second.confidence);
+    (best.confidence >= AUTO_RESOLVE_CONFIDENCE && best.confidence - second >= MIN_CONFIDENCE_GAP)
+        .then_some(best)
+}
+
+/// Lowercase alphanumeric words without filler words, joined without spaces:
+/// `Shitzu Pool` -> `shitzu`, `shitzu.pool.near` -> `shitzu`
+fn normalize_name(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty() && !FILLER_WORDS.contains(word))
+        .collect()
+}
+
+/// How likely `pool_id` is the pool the user means, from 0 to 1
+fn match_confidence(query: &str, query_name: &str, pool_id: &AccountId) -> f64 {
+    if query.trim().eq_ignore_ascii_case(pool_id.as_str()) {
+        return 1.0;
+    }
+    // The name is the part before `.pool.near`, `.poolv1.near`, etc.
+    let pool_name = normalize_name(pool_id.as_str().split('.').next().unwrap_or_default());
+    if query_name.is_empty() || pool_name.is_empty() {
+        return 0.0;
+    }
+    if pool_name == query_name {
+        return 0.95;
+    }
+    let length_ratio =
+        query_name.len().min(pool_name.len()) as f64 / query_name.len().max(pool_name.len()) as f64;
+    if pool_name.starts_with(query_name) || query_name.starts_with(&pool_name) {
+        return 0.6 + 0.3 * length_ratio;
+    }
+    if pool_name.contains(query_name) {
+        return 0.5 + 0.3 * length_ratio;
+    }
+    // Typos
+    let distance = levenshtein(query_name, &pool_name);
+    let similarity = 1.0 - distance as f64 / query_name.len().max(pool_name.len()) as f64;
+    similarity * 0.85
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn describe_candidates(candidates: &[PoolCandidate]) -> String {
+    candidates
+        .iter()
+        .map(|candidate| {
+            let mut notes = vec![format!(
+                "confidence {}%",
+                (candidate.confidence * 100.0).round()
+            )];
+            if candidate.is_delegated {
+                notes.push("the user is staking here".to_string());
+            }
+            if !candidate.is_validator {
+                notes.push("not currently validating".to_string());
+            }
+            format!("- {} ({})", candidate.pool_id, notes.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn ambiguous_message(query: &str, candidates: &[PoolCandidate]) -> String {
+    if candidates.is_empty() {
+        format!("No staking pool matches `{query}`. Ask the user for the full pool account ID, like `name.pool.near` or `name.poolv1.near`, DON'T guess.")
+    } else {
+        format!(
+            "`{query}` could be one of these staking pools, ask the user which one they mean, DON'T guess:\n{}",
+            describe_candidates(candidates)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn confidence(query: &str, pool_id: &str) -> f64 {
+        match_confidence(query, &normalize_name(query), &pool_id.parse().unwrap())
+    }
+
+    fn candidate(pool_id: &str, confidence: f64) -> PoolCandidate {
+        PoolCandidate {
+            pool_id: pool_id.parse().unwrap(),
+            confidence,
+            is_validator: true,
+            is_delegated: false,
+        }
+    }
+
+    #[test]
+    fn normalize_names() {
+        assert_eq!(normalize_name("Shitzu Pool"), "shitzu");
+        assert_eq!(normalize_name("shitzu.pool.near"), "shitzu");
+        assert_eq!(normalize_name("The Intear validator"), "intear");
+        assert_eq!(normalize_name("mintbase.poolv1.near"), "mintbase");
+        assert_eq!(normalize_name("astro-stakers"), "astrostakers");
+        assert_eq!(normalize_name("pool"), "");
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        assert_eq!(levenshtein("intear", "intear"), 0);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("inter", "intear"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn match_confidence_exact() {
+        assert_eq!(confidence("intear.pool.near", "intear.pool.near"), 1.0);
+        assert_eq!(confidence(" Intear.Pool.Near ", "intear.pool.near"), 1.0);
+        assert_eq!(confidence("intear", "intear.pool.near"), 0.95);
+        assert_eq!(confidence("Intear Pool", "intear.pool.near"), 0.95);
+    }
+
+    #[test]
+    fn match_confidence_near_miss() {
+        // Prefix, 3 of 6 characters
+        assert!((confidence("int", "intear.pool.near") - 0.75).abs() < 1e-9);
+        // Typo, 1 edit in 6 characters
+        let typo = confidence("inteer", "intear.pool.near");
+        assert!((typo - 5.0 / 6.0 * 0.85).abs() < 1e-9);
+        assert!((MIN_CONFIDENCE..AUTO_RESOLVE_CONFIDENCE).contains(&typo));
+    }
+
+    #[test]
+    fn match_confidence_no_match() {
+        assert!(confidence("shitzu", "intear.pool.near") < MIN_CONFIDENCE);
+        assert_eq!(confidence("pool", "intear.pool.near"), 0.0);
+        assert_eq!(confidence("", "intear.pool.near"), 0.0);
+    }
+
+    #[test]
+    fn confident_candidates() {
+        let confident = [
+            candidate("intear.pool.near", 0.95),
+            candidate("intearnet.pool.near", 0.8),
+        ];
+        assert_eq!(
+            confident_candidate(&confident).map(|candidate| candidate.pool_id.as_str()),
+            Some("intear.pool.near")
+        );
+        assert!(confident_candidate(&confident[..1]).is_some());
+        // Ambiguous, the second best is too close
+        let ambiguous = [
+            candidate("intear.pool.near", 0.95),
+            candidate("intear.poolv1.near", 0.95),
+        ];
+        assert!(confident_candidate(&ambiguous).is_none());
+        // Not confident enough
+        assert!(confident_candidate(&[candidate("intear.pool.near", 0.8)]).is_none());
+        assert!(confident_candidate(&[]).is_none());
+    }
+}
@@ -1,39 +1,162 @@
-use bigdecimal::{BigDecimal, FromPrimitive};
-use num_traits::ToPrimitive;
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{BalanceAmount, NearAmount},
+        response::ResponseFormat,
+        rpc::{get_spendable_balance, RpcError, SpendableBalance},
+        transaction::{FunctionCallAction, Transaction, TransactionBatch, TransactionResponse},
+        validators::{get_epoch_validators, get_pool_total_stake, is_whitelisted_pool},
+    },
+};
+
+use super::resolve_pool::{resolve_pool, ResolvedPool};
+
+pub const OPERATION: Operation = Operation {
+    path: "stake",
+    operation_id: "stake",
+    summary: "Generate a transaction that stakes NEAR tokens on a given pool",
+    description: "Returns a transaction that stakes NEAR tokens on a given pool. Checks that the pool is a real staking pool and that the user has enough NEAR, and explains the problem otherwise.",
+    response_description: "The transaction that stakes NEAR tokens on a given pool.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<StakeInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct StakeInput {
+    /// The account ID of the user who is staking.
+    #[schemars(with = "String")]
+    pub account_id: AccountId,
+    /// The account ID of the pool to stake on, or its name like `intear`, which is resolved to an account ID. Ask the user to input the pool, usually it ends in `.pool.near` or `.poolv1.near`. Suggest `intear.pool.near` if not provided, or recommend one of these options: 1) intear.pool.near - has 5% fee and gives $pTEAR, a token that will later be converted to $TEAR after the launch, operated by Intear, the developers of this agent, 2) shitzu.pool.near - has 25% fee, and pays additional reward in memecoins, which is usually higher than the fee, but depends on the market, warn the user that memecoins are a volatile asset, 3) mintbase.poolv1.near - has 5% fee, is operated by Bitte, or use the validators tool to find pools by fee, APY, and uptime. There is no slashing on NEAR Protocol, so funds are always safe, worst-case scenario is that the validator goes down and stops generating APY.
     pub pool_account_id: String,
-    pub amount: BigDecimal,
+    /// The amount of NEAR tokens to stake, like `1.5`, `all`, or a percentage of the NEAR that can be staked like `50%`. Some NEAR is always left for gas and storage. If not provided, ask the user if they want to stake all their NEAR or specify an amount.
+    #[schemars(with = "String")]
+    pub amount: BalanceAmount,
+}
+
+/// Whether a pool can be staked in
+enum PoolStatus {
+    Validator,
+    /// Whitelisted, but not validating now, so it doesn't earn rewards
+    NotValidating,
+    /// Explains why the pool can't be used
+    Invalid(String),
 }
 
 pub async fn create_stake_transaction(
     input: StakeInput,
-) -> Result<impl warp::Reply, warp::Rejection> {
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(stake(input).await?.into_reply(format))
+}
+
+async fn stake(input: StakeInput) -> Result<TransactionResponse, AgentError> {
     let StakeInput {
+        account_id,
         pool_account_id,
         amount,
     } = input;
-    let yocto = amount.clone() * BigDecimal::from_u128(10u128.pow(24)).unwrap();
-    let yocto = ToPrimitive::to_u128(&yocto);
-    let Some(yocto) = yocto else {
-        log::warn!("Failed to convert amount to yoctoNEAR: {amount}");
-        return Ok(r#"Say that the agent failed to convert the amount to yoctoNEAR, this is a bug, and to ask in https://t.me/intearchat for support."#.to_string());
+    let pool_account_id = match resolve_pool(&pool_account_id, Some(&account_id)).await? {
+        ResolvedPool::Pool(pool_account_id) => pool_account_id,
+        ResolvedPool::Ambiguous(message) => return Ok(TransactionResponse::message(message)),
+    };
+    let exact_amount = match &amount {
+        BalanceAmount::Exact(exact) => Some(NearAmount::parse_human(exact)?),
+        BalanceAmount::All | BalanceAmount::Percent(_) => None,
     };
-    Ok(format!(
-        r#"
-[
-    {{
-      "methodName": "deposit_and_stake",
-      "args": "{{}}",
-      "gas": "50000000000000",
-      "deposit": "{yocto}",
-      "contractName": "{pool_account_id}"
-    }}
-]
-
-Use this data to call `generate-transaction` tool to generate a transaction.
-    "#
+
+    let mut instructions = Vec::new();
+    match check_pool(&pool_account_id).await? {
+        PoolStatus::Validator => (),
+        PoolStatus::NotValidating => instructions.push(format!(
+            "{pool_account_id} is not validating right now, so the staked NEAR won't earn rewards until it's selected as a validator again."
+        )),
+        PoolStatus::Invalid(message) => return Ok(TransactionResponse::message(message)),
+    }
+
+    let spendable_balance = get_spendable_balance(&account_id)
+        .await
+        .map_err(AgentError::rpc)?;
+    let SpendableBalance {
+        balance,
+        spendable: max_stake,
+        ..
+    } = spendable_balance;
+    let reserved = spendable_balance.describe_reserved();
+    let amount = match (&amount, exact_amount) {
+        (_, Some(exact)) => exact,
+        (BalanceAmount::Percent(percent), None) => max_stake.percent(percent),
+        (_, None) => max_stake,
+    };
+    if amount.is_zero() {
+        return Ok(TransactionResponse::message(format!(
+            "There's no NEAR to stake. The account has {balance}, of which {reserved}."
+        )));
+    }
+    if amount > max_stake {
+        return Ok(TransactionResponse::message(format!(
+            "You can stake at most {max_stake}. The account has {balance}, of which {reserved}.",
+        )));
+    }
+    if exact_amount.is_none() {
+        instructions.push(format!("{amount} will be staked, {reserved}."));
+    }
+    let message = if instructions.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "After that, let the user know that {}",
+            instructions.join(" ")
+        )
+    };
+
+    Ok(TransactionResponse::new(
+        TransactionBatch::single(Transaction::new(pool_account_id).function_call(
+            FunctionCallAction::new("deposit_and_stake", serde_json::json!({}), amount),
+        )),
+        message,
     ))
 }
+
+/// A pool is genuine if it has the staking pool interface and is either a
+/// validator or whitelisted. Anyone can deploy a contract with the same
+/// methods, so the interface alone is not enough.
+async fn check_pool(pool_id: &AccountId) -> Result<PoolStatus, AgentError> {
+    match get_pool_total_stake(pool_id).await {
+        Ok(_) => (),
+        Err(RpcError::UnknownAccount(_)) => {
+            return Ok(PoolStatus::Invalid(format!(
+                "{pool_id} doesn't exist. Ask the user to double-check the pool account ID, or find the pool by name with the resolve-pool tool."
+            )))
+        }
+        Err(
+            RpcError::NoContractCode(_)
+            | RpcError::ContractExecution(_)
+            | RpcError::InvalidResult(_),
+        ) => {
+            return Ok(PoolStatus::Invalid(format!(
+                "{pool_id} is not a staking pool. Ask the user to double-check the pool account ID, usually it ends in `.pool.near` or `.poolv1.near`."
+            )))
+        }
+        Err(err) => return Err(AgentError::rpc(err)),
+    }
+    match get_epoch_validators().await {
+        Ok(epoch) if epoch.is_validator(pool_id) => return Ok(PoolStatus::Validator),
+        Ok(_) => (),
+        Err(err) => log::warn!("Failed to get validators: {err:?}"),
+    }
+    if is_whitelisted_pool(pool_id)
+        .await
+        .map_err(AgentError::rpc)?
+    {
+        Ok(PoolStatus::NotValidating)
+    } else {
+        Ok(PoolStatus::Invalid(format!(
+            "{pool_id} looks like a staking pool, but it's not a validator and not in the staking pool whitelist, so it may be a scam. Don't stake there, suggest a pool from the validators tool instead."
+        )))
+    }
+}
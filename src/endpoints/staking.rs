@@ -1,54 +1,154 @@
+use crate::config::CONFIG;
+use crate::error::AgentError;
+use crate::global_state::get_near_price;
+use crate::manifest::{input_schema, Operation, ResponseBody};
 use crate::utils::{
-    formatting::format_near_amount,
+    amount::NearAmount,
+    formatting::{format_duration, format_near_amount},
+    response::{BalanceJson, ResponseFormat},
     rpc::{get_cached_30s, view_account_cached_30s, view_cached_30s},
+    validators::{get_epoch_progress, EpochProgress},
 };
 
-use std::cmp::Reverse;
+use std::{
+    cmp::Reverse,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use itertools::Itertools;
 use near_primitives::types::AccountId;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Reply;
 
 // For some reason, unstaked amount always goes +1 yoctonear every time you stake
-pub const NOT_STAKING_THRESHOLD: u128 = 1_000;
+pub const NOT_STAKING_THRESHOLD: NearAmount = NearAmount::from_yocto(1_000);
 
-#[derive(Debug, Deserialize)]
+pub const OPERATION: Operation = Operation {
+    path: "staking",
+    operation_id: "staking",
+    summary: "Get the staking information of an account",
+    description: "Returns the total NEAR balance of an account, staked and unstaked NEAR balance, and pool accounts that the user has delegated to, so it can be used for \"word\" -> pool account id conversion for other tools. IMPORTANT: Don't just assume a pool account id unless provided with a full pool account id ending. Try to use this tool before stake / unstake to make sure that it's correct. If you don't find the pool you're looking for, ask the user to input the full pool account id, but DON'T hallucinate.",
+    response_description: "The total balance, staked and unstaked NEAR balance of the user.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<GetStakingInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct GetStakingInput {
+    /// The account ID to retrieve the balance for.
+    #[schemars(with = "String")]
+    pub account_id: AccountId,
+}
+
+/// JSON response of `/staking`
+#[derive(Debug, Serialize)]
+pub struct StakingResponse {
     pub account_id: AccountId,
+    /// Liquid NEAR balance
+    pub near: BalanceJson,
+    /// `null` if staking information couldn't be loaded
+    pub staking: Option<Vec<StakingPositionJson>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StakingPositionJson {
+    pub pool_id: AccountId,
+    pub staked: BalanceJson,
+    /// Unstaked, either still unstaking or ready to withdraw
+    pub unstaked: BalanceJson,
+    /// Whether `unstaked` can be withdrawn now
+    pub is_unstaked_balance_available: bool,
+    /// Set if `unstaked` is still unstaking
+    pub withdrawable: Option<WithdrawableJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WithdrawableJson {
+    /// The latest epoch when `unstaked` can be withdrawn. Pools don't expose
+    /// the unstake epoch, so it's estimated from the last interaction with
+    /// the pool, which is at or after the unstake, and it may be earlier.
+    pub epoch_height: u64,
+    /// Estimated start of the epoch, Unix timestamp in seconds
+    pub estimated_timestamp: u64,
 }
 
-pub async fn get_staking(input: GetStakingInput) -> Result<impl warp::Reply, warp::Rejection> {
+pub async fn get_staking(
+    input: GetStakingInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
     let GetStakingInput { account_id } = input;
     let near_balance = view_account_cached_30s(account_id.clone())
         .await
-        .map_err(|e| {
-            log::warn!("Failed to get NEAR balance of {account_id}: {e:?}");
-            warp::reject::reject()
-        })?
+        .map_err(AgentError::rpc)?
         .amount;
 
-    let staked_near = format_staking_info(&account_id).await;
+    let staking_data = get_delegated_validators(&account_id).await;
 
-    Ok(format!(
-        "
+    Ok(match format {
+        ResponseFormat::Json => warp::reply::json(&StakingResponse {
+            near: BalanceJson::near(near_balance, get_near_price().await.as_ref()),
+            staking: staking_positions_json(&account_id, &staking_data).await,
+            account_id,
+        })
+        .into_response(),
+        ResponseFormat::Text => format!(
+            "
 NEAR balance: {}
 
-Staked NEAR: {staked_near}
+Staked NEAR: {}
         ",
-        format_near_amount(near_balance).await,
-    ))
+            format_near_amount(near_balance).await,
+            format_staking_info(&account_id, &staking_data).await,
+        )
+        .into_response(),
+    })
 }
 
 pub struct StakingData {
     pub pool_id: AccountId,
-    pub staked_amount: u128,
-    pub unstaked_amount: u128,
+    pub staked_amount: NearAmount,
+    pub unstaked_amount: NearAmount,
     pub is_unstaked_balance_available: bool,
+    /// Last block where the account interacted with the pool, according to
+    /// FastNear
+    pub last_update_block_height: Option<u64>,
+}
+
+/// When unstaked NEAR can be withdrawn at the latest
+pub struct Withdrawable {
+    pub epoch_height: u64,
+    pub estimated_time: SystemTime,
+}
+
+impl StakingData {
+    /// `None` if there's nothing unstaking
+    pub fn withdrawable(&self, progress: &EpochProgress) -> Option<Withdrawable> {
+        if self.is_unstaked_balance_available || self.unstaked_amount <= NOT_STAKING_THRESHOLD {
+            return None;
+        }
+        // The last interaction is usually the unstake. If it's a later one,
+        // like staking more, the estimate is later than the real epoch, so
+        // it's always an upper bound.
+        let epoch_height = progress.unstake_available_epoch(self.last_update_block_height);
+        Some(Withdrawable {
+            epoch_height,
+            estimated_time: progress.epoch_start_time(epoch_height),
+        })
+    }
+}
+
+/// Response of `get_account` of a staking pool
+#[derive(Debug, Deserialize)]
+struct PoolAccount {
+    staked_balance: NearAmount,
+    unstaked_balance: NearAmount,
+    can_withdraw: bool,
 }
 
 pub async fn get_delegated_validators(
     account_id: &AccountId,
-) -> Result<Vec<StakingData>, anyhow::Error> {
+) -> Result<Vec<StakingData>, AgentError> {
     #[derive(Debug, Deserialize)]
     struct Response {
         pools: Vec<Pool>,
@@ -59,113 +159,116 @@ pub async fn get_delegated_validators(
     #[derive(Debug, Deserialize)]
     struct Pool {
         pool_id: AccountId,
-        #[allow(dead_code)]
         last_update_block_height: Option<u64>,
     }
 
-    let url = format!("https://api.fastnear.com/v1/account/{account_id}/staking");
+    let url = format!(
+        "{}/v1/account/{account_id}/staking",
+        CONFIG.fastnear_api_url
+    );
     match get_cached_30s::<Response>(&url).await {
         Ok(response) => {
-            let pools = response.pools.into_iter().map(|pool| pool.pool_id);
-            let mut amounts = Vec::new();
-            for pool_id in pools {
-                amounts.push(async {
-                    let amount_staked = view_cached_30s::<_, String>(
-                        &pool_id,
-                        "get_account_staked_balance",
-                        serde_json::json!({"account_id": account_id}),
-                    )
-                    .await
-                    .map(|balance| balance.parse::<u128>().unwrap_or_default());
-                    let amount_unstaked = view_cached_30s::<_, String>(
-                        &pool_id,
-                        "get_account_unstaked_balance",
-                        serde_json::json!({"account_id": account_id}),
-                    )
-                    .await
-                    .map(|balance| balance.parse::<u128>().unwrap_or_default());
-
-                    if let Ok(NOT_STAKING_THRESHOLD..) = amount_unstaked {
-                        let is_unstaked_balance_available = view_cached_30s::<_, bool>(
-                            &pool_id,
-                            "is_account_unstaked_balance_available",
-                            serde_json::json!({"account_id": account_id}),
-                        )
-                        .await;
-                        (
-                            pool_id,
-                            amount_staked,
-                            amount_unstaked,
-                            is_unstaked_balance_available,
-                        )
-                    } else {
-                        (pool_id, amount_staked, Ok(0), Ok(false))
-                    }
-                });
-            }
-            futures_util::future::join_all(amounts)
-                .await
-                .into_iter()
-                .map(
-                    |(pool_id, staked_amount, unstaked_amount, is_unstaked_balance_available)| {
-                        match (
-                            staked_amount,
-                            unstaked_amount,
-                            is_unstaked_balance_available,
-                        ) {
-                            (
-                                Ok(staked_amount),
-                                Ok(unstaked_amount),
-                                Ok(is_unstaked_balance_available),
-                            ) => Ok(StakingData {
-                                pool_id,
-                                staked_amount,
-                                unstaked_amount,
-                                is_unstaked_balance_available,
-                            }),
-                            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => Err(e),
-                        }
-                    },
+            futures_util::future::join_all(response.pools.into_iter().map(|pool| async move {
+                let account = view_cached_30s::<_, PoolAccount>(
+                    &pool.pool_id,
+                    "get_account",
+                    serde_json::json!({"account_id": account_id}),
                 )
-                .collect()
+                .await
+                .map_err(AgentError::rpc)?;
+                Ok(StakingData {
+                    pool_id: pool.pool_id,
+                    staked_amount: account.staked_balance,
+                    unstaked_amount: account.unstaked_balance,
+                    is_unstaked_balance_available: account.can_withdraw,
+                    last_update_block_height: pool.last_update_block_height,
+                })
+            }))
+            .await
+            .into_iter()
+            .collect()
         }
         Err(e) => {
             log::warn!("Failed to get validators delegated by {account_id}: {e:?}");
-            Err(e)
+            Err(AgentError::fastnear(e))
         }
     }
 }
 
-pub async fn format_staking_info(account_id: &AccountId) -> String {
-    let staked_near = get_delegated_validators(account_id).await;
-    let staked_near = match staked_near {
+/// Positions with nothing staked or unstaked are skipped, largest first
+fn active_positions(staking_data: &[StakingData]) -> impl Iterator<Item = &StakingData> {
+    staking_data
+        .iter()
+        .filter(|d| !d.staked_amount.is_zero() || !d.unstaked_amount.is_zero())
+        .sorted_by_key(|d| Reverse(d.staked_amount + d.unstaked_amount))
+}
+
+pub async fn staking_positions_json(
+    account_id: &AccountId,
+    staking_data: &Result<Vec<StakingData>, AgentError>,
+) -> Option<Vec<StakingPositionJson>> {
+    let staking_data = match staking_data {
+        Ok(staking_data) => staking_data,
+        Err(e) => {
+            log::warn!("Failed to get staked NEAR of {account_id}: {e:?}");
+            return None;
+        }
+    };
+    let near_price = get_near_price().await;
+    let progress = get_unstaking_progress(staking_data).await;
+    Some(
+        active_positions(staking_data)
+            .map(|d| StakingPositionJson {
+                pool_id: d.pool_id.clone(),
+                staked: BalanceJson::near(d.staked_amount, near_price.as_ref()),
+                unstaked: BalanceJson::near(d.unstaked_amount, near_price.as_ref()),
+                is_unstaked_balance_available: d.is_unstaked_balance_available,
+                withdrawable: progress
+                    .as_ref()
+                    .and_then(|progress| d.withdrawable(progress))
+                    .map(|withdrawable| WithdrawableJson {
+                        epoch_height: withdrawable.epoch_height,
+                        estimated_timestamp: withdrawable
+                            .estimated_time
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                    }),
+            })
+            .collect(),
+    )
+}
+
+pub async fn format_staking_info(
+    account_id: &AccountId,
+    staking_data: &Result<Vec<StakingData>, AgentError>,
+) -> String {
+    let staked_near = match staking_data {
         Ok(staked_near) => {
+            let progress = get_unstaking_progress(staked_near).await;
             let mut staked_near_str = String::new();
-            for StakingData {
+            for data @ StakingData {
                 pool_id,
                 staked_amount,
                 unstaked_amount,
                 is_unstaked_balance_available,
-            } in staked_near
-                .into_iter()
-                .filter(|d| d.staked_amount != 0 || d.unstaked_amount != 0)
-                .sorted_by_key(|d| Reverse(d.staked_amount + d.unstaked_amount))
+                ..
+            } in active_positions(staked_near)
             {
                 staked_near_str.push_str(&format!(
                     "\n- {pool_id} : *{staked_amount}*{unstaked}",
-                    staked_amount = format_near_amount(staked_amount).await,
-                    // For some reason, unstaked amount always goes +1 yoctonear every time you stake
-                    unstaked = if unstaked_amount <= 1_000 {
+                    staked_amount = format_near_amount(*staked_amount).await,
+                    unstaked = if *unstaked_amount <= NOT_STAKING_THRESHOLD {
                         "".to_string()
                     } else {
                         format!(
                             ". {availability} *{unstaked}*",
-                            availability = if is_unstaked_balance_available {
-                                "Unstaked and ready to claim"
+                            availability = if *is_unstaked_balance_available {
+                                "Unstaked and ready to claim".to_string()
                             } else {
-                                "Currently in the process of unstaking, will be available in 2-3 days"
+                                describe_unstaking(data, progress.as_ref())
                             },
-                            unstaked = format_near_amount(unstaked_amount).await,
+                            unstaked = format_near_amount(*unstaked_amount).await,
                         )
                     }
                 ));
@@ -183,3 +286,39 @@ pub async fn format_staking_info(account_id: &AccountId) -> String {
         staked_near
     }
 }
+
+/// Epoch progress if any NEAR is unstaking, to estimate when it's available
+pub async fn get_unstaking_progress(staking_data: &[StakingData]) -> Option<EpochProgress> {
+    if !staking_data
+        .iter()
+        .any(|d| !d.is_unstaked_balance_available && d.unstaked_amount > NOT_STAKING_THRESHOLD)
+    {
+        return None;
+    }
+    match get_epoch_progress().await {
+        Ok(progress) => Some(progress),
+        Err(e) => {
+            log::warn!("Failed to get epoch progress: {e:?}");
+            None
+        }
+    }
+}
+
+pub fn describe_unstaking(data: &StakingData, progress: Option<&EpochProgress>) -> String {
+    let Some((progress, withdrawable)) =
+        progress.and_then(|progress| Some((progress, data.withdrawable(progress)?)))
+    else {
+        return "Currently in the process of unstaking, will be available in 2-3 days".to_string();
+    };
+    format!(
+        "Currently in the process of unstaking, will be available at the latest in epoch {} (the current epoch is {}), by ~{} from now",
+        withdrawable.epoch_height,
+        progress.epoch_height,
+        format_duration(
+            withdrawable
+                .estimated_time
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        ),
+    )
+}
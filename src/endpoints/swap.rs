@@ -0,0 +1,204 @@
+use bigdecimal::BigDecimal;
+use near_primitives::types::{AccountId, Gas};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{HumanAmount, NearAmount, TokenAmount},
+        ft::{ft_balance_of, storage_deposit_needed},
+        ref_finance::{swap_msg, REF_FINANCE_CONTRACT},
+        response::ResponseFormat,
+        rpc::get_spendable_balance,
+        transaction::{FunctionCallAction, Transaction, TransactionBatch, TransactionResponse},
+    },
+};
+
+use super::swap_quote::{get_quote, parse_amount, resolve_swap_token};
+
+/// Enough for a two-hop swap and sending the output to the user
+const SWAP_GAS: Gas = 180_000_000_000_000;
+
+pub const OPERATION: Operation = Operation {
+    path: "swap",
+    operation_id: "swap",
+    summary: "Generate a transaction that swaps tokens on Ref Finance",
+    description: "Returns the transactions that swap tokens on Ref Finance, including wrapping NEAR and storage registration if needed. Get a quote with swap-quote first and confirm it with the user.",
+    response_description: "The transactions that swap the tokens.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<SwapInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SwapInput {
+    /// The account ID of the user who is swapping.
+    #[schemars(with = "String")]
+    pub account_id: AccountId,
+    /// The token to sell: `NEAR`, a ticker, a name, or a token contract address.
+    pub token_in: String,
+    /// The token to buy: `NEAR`, a ticker, a name, or a token contract address.
+    pub token_out: String,
+    /// The amount of `token_in` to sell, like `1.5`, or `all` to sell the whole balance of a fungible token.
+    #[schemars(with = "String")]
+    pub amount_in: HumanAmount,
+    /// Maximum slippage in percent, like `0.5`. Defaults to 0.5%.
+    #[schemars(with = "Option<f64>")]
+    pub slippage: Option<BigDecimal>,
+}
+
+pub async fn create_swap_transaction(
+    input: SwapInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(swap(input).await?.into_reply(format))
+}
+
+async fn swap(input: SwapInput) -> Result<TransactionResponse, AgentError> {
+    let SwapInput {
+        account_id,
+        token_in,
+        token_out,
+        amount_in,
+        slippage,
+    } = input;
+    let token_in = resolve_swap_token(&token_in).await?;
+    let token_out = resolve_swap_token(&token_out).await?;
+
+    let mut batch = TransactionBatch::default();
+    let mut registrations = Vec::new();
+
+    if token_in.is_near {
+        let HumanAmount::Exact(amount) = &amount_in else {
+            return Ok(TransactionResponse::message(
+                "Swapping all NEAR is not supported, because some NEAR is needed for gas. Ask the user to specify an amount.",
+            ));
+        };
+        let amount = NearAmount::from_yocto(parse_amount(amount, &token_in)?);
+        let spendable_balance = get_spendable_balance(&account_id)
+            .await
+            .map_err(AgentError::rpc)?;
+        let available = spendable_balance.spendable;
+        let storage_deposit = storage_deposit_needed(&token_in.contract_id, &account_id)
+            .await
+            .map_err(AgentError::rpc)?;
+        if amount + storage_deposit.unwrap_or(NearAmount::ZERO) > available {
+            return Ok(TransactionResponse::message(format!(
+                "You only have {available} available, which is not enough to swap {amount}{}. The account has {}, of which {}.",
+                match storage_deposit {
+                    Some(deposit) => format!(" and pay {deposit} for wNEAR storage"),
+                    None => "".to_string(),
+                },
+                spendable_balance.balance,
+                spendable_balance.describe_reserved(),
+            )));
+        }
+        let mut wrap = Transaction::new(token_in.contract_id.clone());
+        if let Some(deposit) = storage_deposit {
+            wrap = wrap.function_call(FunctionCallAction::storage_deposit(&account_id, deposit));
+        }
+        batch.push(wrap.function_call(FunctionCallAction::new(
+            "near_deposit",
+            serde_json::json!({}),
+            amount,
+        )));
+    }
+    let amount_in = match amount_in {
+        HumanAmount::Exact(amount) => {
+            let amount = parse_amount(&amount, &token_in)?;
+            if !token_in.is_near {
+                let balance = ft_balance_of(&token_in.contract_id, &account_id)
+                    .await
+                    .map_err(AgentError::rpc)?;
+                if amount > balance {
+                    return Ok(TransactionResponse::message(format!(
+                        "You only have {}.",
+                        TokenAmount::from_raw(balance, token_in.decimals).format(&token_in.symbol)
+                    )));
+                }
+            }
+            amount
+        }
+        HumanAmount::All => {
+            let balance = ft_balance_of(&token_in.contract_id, &account_id)
+                .await
+                .map_err(AgentError::rpc)?;
+            if balance == 0 {
+                return Ok(TransactionResponse::message(format!(
+                    "You don't have any {}.",
+                    token_in.symbol
+                )));
+            }
+            balance
+        }
+    };
+
+    let quote = get_quote(token_in, token_out, amount_in, slippage).await?;
+
+    let ref_finance: AccountId = REF_FINANCE_CONTRACT.parse().unwrap();
+    if let Some(deposit) = storage_deposit_needed(&ref_finance, &account_id)
+        .await
+        .map_err(AgentError::rpc)?
+    {
+        batch.push(
+            Transaction::new(ref_finance.clone())
+                .function_call(FunctionCallAction::storage_deposit(&account_id, deposit)),
+        );
+        registrations.push(format!("{deposit} to register on Ref Finance"));
+    }
+    if let Some(deposit) = storage_deposit_needed(&quote.token_out.contract_id, &account_id)
+        .await
+        .map_err(AgentError::rpc)?
+    {
+        batch.push(
+            Transaction::new(quote.token_out.contract_id.clone())
+                .function_call(FunctionCallAction::storage_deposit(&account_id, deposit)),
+        );
+        registrations.push(format!(
+            "{deposit} to register on {}",
+            quote.token_out.contract_id
+        ));
+    }
+    batch.push(
+        Transaction::new(quote.token_in.contract_id.clone()).function_call(
+            FunctionCallAction::new(
+                "ft_transfer_call",
+                serde_json::json!({
+                    "receiver_id": ref_finance,
+                    "amount": amount_in.to_string(),
+                    "msg": swap_msg(&quote.route, quote.min_amount_out),
+                }),
+                NearAmount::from_yocto(1),
+            )
+            .with_gas(SWAP_GAS),
+        ),
+    );
+    if quote.token_out.is_near {
+        // Only the minimum is guaranteed, the rest stays as wNEAR
+        batch.push(
+            Transaction::new(quote.token_out.contract_id.clone()).function_call(
+                FunctionCallAction::new(
+                    "near_withdraw",
+                    serde_json::json!({ "amount": quote.min_amount_out.to_string() }),
+                    NearAmount::from_yocto(1),
+                ),
+            ),
+        );
+    }
+
+    let mut message = format!(
+        "After that, show the user the quote:\n{}",
+        quote.describe().await
+    );
+    if !registrations.is_empty() {
+        message.push_str(&format!(
+            "\nAlso let them know that the transactions pay {} for storage.",
+            registrations.join(" and ")
+        ));
+    }
+    if quote.token_out.is_near {
+        message.push_str("\nAny NEAR received above the minimum stays as wNEAR, let the user know that they can unwrap it.");
+    }
+    Ok(TransactionResponse::new(batch, message))
+}
@@ -0,0 +1,244 @@
+use bigdecimal::{BigDecimal, FromPrimitive, RoundingMode, ToPrimitive};
+use near_primitives::serialize::dec_format;
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Reply;
+
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{to_plain_string, HumanAmount, TokenAmount},
+        formatting::{NEAR_DECIMALS, WRAP_NEAR},
+        ft::resolve_ft_metadata,
+        ref_finance::{best_route, get_pools, Hop, Route},
+        response::ResponseFormat,
+    },
+};
+
+use super::token_prices::{resolve_token, ResolvedToken};
+
+/// Used if the user doesn't specify slippage
+const DEFAULT_SLIPPAGE_PERCENT: f64 = 0.5;
+const MAX_SLIPPAGE_PERCENT: u32 = 50;
+
+pub const OPERATION: Operation = Operation {
+    path: "swap-quote",
+    operation_id: "swapQuote",
+    summary: "Get a quote for swapping tokens on Ref Finance",
+    description: "Returns how much of the output token the user would get for the input amount, the route, price impact, and the minimum amount received with slippage. Use it to show the user what they will get before generating a swap transaction.",
+    response_description: "The expected output, route, price impact, and minimum amount received.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<SwapQuoteInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SwapQuoteInput {
+    /// The token to sell: `NEAR`, a ticker, a name, or a token contract address.
+    pub token_in: String,
+    /// The token to buy: `NEAR`, a ticker, a name, or a token contract address.
+    pub token_out: String,
+    /// The amount of `token_in` to sell, like `1.5`.
+    #[schemars(with = "String")]
+    pub amount_in: HumanAmount,
+    /// Maximum slippage in percent, like `0.5`. Defaults to 0.5%.
+    #[schemars(with = "Option<f64>")]
+    pub slippage: Option<BigDecimal>,
+}
+
+/// A token being swapped. NEAR is swapped as wNEAR, wrapped or unwrapped in
+/// the same batch of transactions.
+pub struct SwapToken {
+    pub contract_id: AccountId,
+    pub symbol: String,
+    pub decimals: u32,
+    pub is_near: bool,
+}
+
+pub struct Quote {
+    pub token_in: SwapToken,
+    pub token_out: SwapToken,
+    pub route: Route,
+    pub slippage_percent: BigDecimal,
+    pub min_amount_out: u128,
+}
+
+/// JSON response of `/swap-quote`
+#[derive(Debug, Serialize)]
+pub struct QuoteJson {
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    #[serde(with = "dec_format")]
+    pub amount_in: u128,
+    #[serde(with = "dec_format")]
+    pub amount_out: u128,
+    #[serde(with = "dec_format")]
+    pub min_amount_out: u128,
+    pub price_impact_percent: String,
+    pub slippage_percent: String,
+    pub route: Vec<Hop>,
+}
+
+pub async fn get_swap_quote(
+    input: SwapQuoteInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let SwapQuoteInput {
+        token_in,
+        token_out,
+        amount_in,
+        slippage,
+    } = input;
+    let HumanAmount::Exact(amount_in) = amount_in else {
+        return Err(AgentError::InvalidInput(
+            "A quote needs an exact amount, ask the user how much they want to swap".to_string(),
+        )
+        .into());
+    };
+    let token_in = resolve_swap_token(&token_in).await?;
+    let token_out = resolve_swap_token(&token_out).await?;
+    let amount_in = parse_amount(&amount_in, &token_in)?;
+    let quote = get_quote(token_in, token_out, amount_in, slippage).await?;
+    Ok(match format {
+        ResponseFormat::Json => warp::reply::json(&quote.to_json()).into_response(),
+        ResponseFormat::Text => quote.describe().await.into_response(),
+    })
+}
+
+pub async fn resolve_swap_token(query: &str) -> Result<SwapToken, AgentError> {
+    Ok(match resolve_token(query).await? {
+        ResolvedToken::Near => SwapToken {
+            contract_id: WRAP_NEAR.parse().unwrap(),
+            symbol: "NEAR".to_string(),
+            decimals: NEAR_DECIMALS,
+            is_near: true,
+        },
+        ResolvedToken::Ft {
+            contract_id,
+            metadata,
+        } => SwapToken {
+            contract_id,
+            symbol: metadata.symbol,
+            decimals: metadata.decimals,
+            is_near: false,
+        },
+    })
+}
+
+pub fn parse_amount(amount: &BigDecimal, token: &SwapToken) -> Result<u128, AgentError> {
+    TokenAmount::parse_human(amount, token.decimals, &token.symbol).map(TokenAmount::raw)
+}
+
+pub async fn get_quote(
+    token_in: SwapToken,
+    token_out: SwapToken,
+    amount_in: u128,
+    slippage_percent: Option<BigDecimal>,
+) -> Result<Quote, AgentError> {
+    let slippage_percent =
+        slippage_percent.unwrap_or_else(|| BigDecimal::from_f64(DEFAULT_SLIPPAGE_PERCENT).unwrap());
+    if slippage_percent <= BigDecimal::from(0)
+        || slippage_percent > BigDecimal::from(MAX_SLIPPAGE_PERCENT)
+    {
+        return Err(AgentError::InvalidInput(format!(
+            "Slippage must be more than 0% and at most {MAX_SLIPPAGE_PERCENT}%"
+        )));
+    }
+    if token_in.contract_id == token_out.contract_id {
+        return Err(AgentError::InvalidInput(
+            if token_in.is_near || token_out.is_near {
+                "NEAR and wNEAR are converted 1:1, use wrap or unwrap instead of swapping"
+                    .to_string()
+            } else {
+                format!("Can't swap {} to itself", token_in.symbol)
+            },
+        ));
+    }
+    if amount_in == 0 {
+        return Err(AgentError::InvalidInput(
+            "Amount to swap must be more than 0".to_string(),
+        ));
+    }
+
+    let pools = get_pools().await.map_err(AgentError::rpc)?;
+    let Some(route) = best_route(
+        &pools,
+        &token_in.contract_id,
+        &token_out.contract_id,
+        amount_in,
+    ) else {
+        return Err(AgentError::InvalidInput(format!(
+            "There's no liquidity to swap {} to {} on Ref Finance",
+            token_in.symbol, token_out.symbol
+        )));
+    };
+    let min_amount_out = (BigDecimal::from(route.amount_out())
+        * (BigDecimal::from(100) - &slippage_percent)
+        / BigDecimal::from(100))
+    .with_scale_round(0, RoundingMode::Down)
+    .to_u128()
+    .unwrap_or_default();
+    Ok(Quote {
+        token_in,
+        token_out,
+        route,
+        slippage_percent,
+        min_amount_out,
+    })
+}
+
+impl Quote {
+    pub fn to_json(&self) -> QuoteJson {
+        QuoteJson {
+            token_in: self.token_in.contract_id.clone(),
+            token_out: self.token_out.contract_id.clone(),
+            amount_in: self.route.amount_in(),
+            amount_out: self.route.amount_out(),
+            min_amount_out: self.min_amount_out,
+            price_impact_percent: self.price_impact_percent(),
+            slippage_percent: to_plain_string(&self.slippage_percent),
+            route: self.route.hops.clone(),
+        }
+    }
+
+    fn price_impact_percent(&self) -> String {
+        to_plain_string(
+            &(&self.route.price_impact * BigDecimal::from(100))
+                .with_scale_round(2, RoundingMode::HalfUp),
+        )
+    }
+
+    /// Text for the LLM
+    pub async fn describe(&self) -> String {
+        let mut path = vec![self.token_in.symbol.clone()];
+        for hop in &self.route.hops[..self.route.hops.len() - 1] {
+            path.push(match resolve_ft_metadata(&hop.token_out).await {
+                Some(metadata) => metadata.symbol,
+                None => hop.token_out.to_string(),
+            });
+        }
+        path.push(self.token_out.symbol.clone());
+        format!(
+            "Swap {} for about {} on Ref Finance
+Route: {} (Ref Finance pool IDs: {})
+Price impact: {}%
+Minimum received with {}% slippage: {}",
+            TokenAmount::from_raw(self.route.amount_in(), self.token_in.decimals)
+                .format(&self.token_in.symbol),
+            TokenAmount::from_raw(self.route.amount_out(), self.token_out.decimals)
+                .format(&self.token_out.symbol),
+            path.join(" -> "),
+            self.route
+                .hops
+                .iter()
+                .map(|hop| hop.pool_id.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.price_impact_percent(),
+            to_plain_string(&self.slippage_percent),
+            TokenAmount::from_raw(self.min_amount_out, self.token_out.decimals)
+                .format(&self.token_out.symbol),
+        )
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bigdecimal::{BigDecimal, RoundingMode, Zero};
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Reply;
+
+use crate::{
+    config::CONFIG,
+    error::AgentError,
+    global_state::{get_ft_price, get_local_price_history, PricePoint},
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::to_plain_string,
+        formatting::{format_usd_amount, WRAP_NEAR},
+        response::ResponseFormat,
+        rpc::get_cached_30s,
+    },
+};
+
+use super::token_prices::{resolve_token, ResolvedToken};
+
+/// Number of points in the returned series, the history is downsampled to
+/// this
+const SERIES_POINTS: u32 = 24;
+/// A change is only computed if there's a price within this fraction of the
+/// period from its start, so that 2 hours of local history aren't reported as
+/// a 30d change
+const PERIOD_START_TOLERANCE_FRACTION: u32 = 10;
+
+pub const OPERATION: Operation = Operation {
+    path: "token-history",
+    operation_id: "getTokenHistory",
+    summary: "Get the price history of a token",
+    description: "Returns the current price of a token, its price change over the last 1 hour, 24 hours, 7 days, and 30 days, and its price over the requested period.",
+    response_description: "The current price, price changes, and price history of the token.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<TokenHistoryInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TokenHistoryInput {
+    /// The token: `NEAR`, a ticker, a name, or a token contract address.
+    pub token: String,
+    /// The period of the price history: `1h`, `24h`, `7d`, or `30d`. Defaults to `7d`.
+    pub period: Option<HistoryPeriod>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum HistoryPeriod {
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl HistoryPeriod {
+    const ALL: [HistoryPeriod; 4] = [
+        HistoryPeriod::Hour,
+        HistoryPeriod::Day,
+        HistoryPeriod::Week,
+        HistoryPeriod::Month,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            HistoryPeriod::Hour => "1h",
+            HistoryPeriod::Day => "24h",
+            HistoryPeriod::Week => "7d",
+            HistoryPeriod::Month => "30d",
+        }
+    }
+
+    fn duration(self) -> Duration {
+        Duration::from_secs(match self {
+            HistoryPeriod::Hour => 60 * 60,
+            HistoryPeriod::Day => 24 * 60 * 60,
+            HistoryPeriod::Week => 7 * 24 * 60 * 60,
+            HistoryPeriod::Month => 30 * 24 * 60 * 60,
+        })
+    }
+}
+
+/// Where the history of a period came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistorySource {
+    PriceService,
+    /// Prices recorded by this server, only as old as its uptime
+    Local,
+}
+
+/// JSON response of `/token-history`
+#[derive(Debug, Serialize)]
+pub struct TokenHistoryJson {
+    pub contract_id: AccountId,
+    pub symbol: String,
+    pub price_usd: String,
+    pub changes: Vec<PriceChangeJson>,
+    pub period: HistoryPeriod,
+    /// Oldest first, at most 24 points, the last one is the current price
+    pub series: Vec<PricePointJson>,
+    pub source: HistorySource,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PriceChangeJson {
+    pub period: HistoryPeriod,
+    /// `null` if there's not enough history
+    pub change_percent: Option<String>,
+    pub source: HistorySource,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PricePointJson {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub price_usd: String,
+}
+
+/// A point of the price service's `/price-history` response, see
+/// `fetch_price_history`
+#[derive(Debug, Deserialize)]
+struct UpstreamPricePoint {
+    /// Unix timestamp in seconds
+    timestamp: u64,
+    price_usd: String,
+}
+
+struct PeriodHistory {
+    period: HistoryPeriod,
+    points: Vec<PricePoint>,
+    source: HistorySource,
+}
+
+pub async fn get_token_history(
+    input: TokenHistoryInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let TokenHistoryInput { token, period } = input;
+    let period = period.unwrap_or(HistoryPeriod::Week);
+    let (contract_id, symbol) = match resolve_token(&token).await? {
+        ResolvedToken::Near => (WRAP_NEAR.parse::<AccountId>().unwrap(), "NEAR".to_string()),
+        ResolvedToken::Ft {
+            contract_id,
+            metadata,
+        } => (contract_id, metadata.symbol),
+    };
+
+    let now = SystemTime::now();
+    let histories = futures_util::future::join_all(
+        HistoryPeriod::ALL.map(|period| get_period_history(&contract_id, period, now)),
+    )
+    .await;
+    let Some(price_usd) = get_ft_price(&contract_id).await.or_else(|| {
+        histories
+            .iter()
+            .find_map(|history| history.points.last())
+            .map(|point| point.price_usd.clone())
+    }) else {
+        return Err(AgentError::InvalidInput(format!(
+            "There's no price information for {symbol} ({contract_id})"
+        ))
+        .into());
+    };
+
+    let changes = histories
+        .iter()
+        .map(|history| PriceChangeJson {
+            period: history.period,
+            change_percent: price_change(history, &price_usd, now)
+                .map(|change| to_plain_string(&change.with_scale_round(2, RoundingMode::HalfUp))),
+            source: history.source,
+        })
+        .collect::<Vec<_>>();
+    let history = histories
+        .iter()
+        .find(|history| history.period == period)
+        .unwrap();
+    let series = downsample(&history.points, period, now, &price_usd);
+
+    if format == ResponseFormat::Json {
+        return Ok(warp::reply::json(&TokenHistoryJson {
+            contract_id,
+            symbol,
+            price_usd: to_plain_string(&price_usd),
+            changes,
+            period,
+            series: series
+                .iter()
+                .map(|point| PricePointJson {
+                    timestamp: point
+                        .timestamp
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                    price_usd: to_plain_string(&point.price_usd),
+                })
+                .collect(),
+            source: history.source,
+        })
+        .into_response());
+    }
+
+    let changes = changes
+        .iter()
+        .map(|change| {
+            format!(
+                "{} {}",
+                change.period.as_str(),
+                match &change.change_percent {
+                    Some(percent) if percent.starts_with('-') => format!("{percent}%"),
+                    Some(percent) => format!("+{percent}%"),
+                    None => "unknown".to_string(),
+                }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let series = series
+        .iter()
+        .map(|point| {
+            format!(
+                "{}: {}",
+                format_ago(now.duration_since(point.timestamp).unwrap_or_default()),
+                format_usd_amount(&point.price_usd)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let note = if histories
+        .iter()
+        .any(|history| history.source == HistorySource::Local)
+    {
+        "\nSome of the history only covers the time since this server started."
+    } else {
+        ""
+    };
+    Ok(format!(
+        "{symbol} ({contract_id}) price: {}
+Change: {changes}
+Price over the last {}:
+{series}{note}",
+        format_usd_amount(&price_usd),
+        period.as_str(),
+    )
+    .into_response())
+}
+
+/// History from the price service, or recorded locally if it's unavailable
+async fn get_period_history(
+    token: &AccountId,
+    period: HistoryPeriod,
+    now: SystemTime,
+) -> PeriodHistory {
+    let duration = period.duration();
+    // A bit before the period, to have a price at its start
+    let since = now - duration - duration / PERIOD_START_TOLERANCE_FRACTION;
+    let result = match &CONFIG.price_history_api_url {
+        Some(api_url) => fetch_price_history(api_url, token, period).await,
+        None => Ok(Vec::new()),
+    };
+    match result {
+        Ok(points) if !points.is_empty() => PeriodHistory {
+            period,
+            points: points
+                .into_iter()
+                .filter(|point| point.timestamp >= since)
+                .collect(),
+            source: HistorySource::PriceService,
+        },
+        result => {
+            if let Err(err) = result {
+                log::warn!("Failed to get {period:?} price history of {token}: {err:?}");
+            }
+            PeriodHistory {
+                period,
+                points: get_local_price_history(token, since).await,
+                source: HistorySource::Local,
+            }
+        }
+    }
+}
+
+/// Prices of `token` over `period` from the price service, oldest first.
+/// Only used if `price_history_api_url` is set, since prices.intear.tech
+/// doesn't document this endpoint. This is the contract it relies on:
+/// `GET {price_history_api_url}/price-history?token_id=<contract>&period=<1h|24h|7d|30d>`
+/// returns a JSON array of `{"timestamp": <unix seconds>, "price_usd": "<decimal>"}`,
+/// in any order, with a resolution that depends on the period. A 404 or an
+/// empty array means there's no history for the token, and the local history
+/// is used instead.
+///
+/// `/token-history` requests all 4 periods to compute the changes, each
+/// response is cached for `cache.http_ttl_secs`, so repeated requests for the
+/// same token don't reach the price service.
+async fn fetch_price_history(
+    api_url: &str,
+    token: &AccountId,
+    period: HistoryPeriod,
+) -> Result<Vec<PricePoint>, anyhow::Error> {
+    let points: Vec<UpstreamPricePoint> = get_cached_30s(&format!(
+        "{api_url}/price-history?token_id={token}&period={}",
+        period.as_str()
+    ))
+    .await?;
+    let mut points = points
+        .into_iter()
+        .map(|point| {
+            Ok(PricePoint {
+                timestamp: UNIX_EPOCH + Duration::from_secs(point.timestamp),
+                price_usd: BigDecimal::from_str(&point.price_usd)?,
+            })
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    points.sort_by_key(|point| point.timestamp);
+    Ok(points)
+}
+
+/// Change from the price closest to the start of the period to `price_usd`
+/// in percent, `None` if there's no price close enough
+fn price_change(
+    history: &PeriodHistory,
+    price_usd: &BigDecimal,
+    now: SystemTime,
+) -> Option<BigDecimal> {
+    let period = history.period.duration();
+    let start = now - period;
+    let distance_to_start = |point: &PricePoint| {
+        point
+            .timestamp
+            .duration_since(start)
+            .unwrap_or_else(|err| err.duration())
+    };
+    let base = history
+        .points
+        .iter()
+        .filter(|point| distance_to_start(point) <= period / PERIOD_START_TOLERANCE_FRACTION)
+        .min_by_key(|point| distance_to_start(point))?;
+    if base.price_usd.is_zero() {
+        return None;
+    }
+    Some((price_usd - &base.price_usd) * BigDecimal::from(100) / &base.price_usd)
+}
+
+/// Splits the period into `SERIES_POINTS` equal intervals and takes the last
+/// price in each of them. The last interval ends now, so its point is the
+/// current price.
+fn downsample(
+    points: &[PricePoint],
+    period: HistoryPeriod,
+    now: SystemTime,
+    price_usd: &BigDecimal,
+) -> Vec<PricePoint> {
+    let start = now - period.duration();
+    let interval = period.duration() / SERIES_POINTS;
+    let mut series = (1..SERIES_POINTS)
+        .filter_map(|i| {
+            let interval_start = start + interval * (i - 1);
+            let interval_end = start + interval * i;
+            points
+                .iter()
+                .rfind(|point| point.timestamp >= interval_start && point.timestamp < interval_end)
+                .cloned()
+        })
+        .collect::<Vec<_>>();
+    series.push(PricePoint {
+        timestamp: now,
+        price_usd: price_usd.clone(),
+    });
+    series
+}
+
+/// `just now`, `5m ago`, `3h ago`, `2d ago`
+fn format_ago(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    if minutes == 0 {
+        "just now".to_string()
+    } else if minutes < 60 {
+        format!("{minutes}m ago")
+    } else if minutes < 48 * 60 {
+        format!("{}h ago", minutes / 60)
+    } else {
+        format!("{}d ago", minutes / (24 * 60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp: SystemTime, price_usd: &str) -> PricePoint {
+        PricePoint {
+            timestamp,
+            price_usd: BigDecimal::from_str(price_usd).unwrap(),
+        }
+    }
+
+    fn history(period: HistoryPeriod, points: Vec<PricePoint>) -> PeriodHistory {
+        PeriodHistory {
+            period,
+            points,
+            source: HistorySource::PriceService,
+        }
+    }
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn price_change_from_closest_to_start() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let start = now - HistoryPeriod::Day.duration();
+        let price_usd = BigDecimal::from(3);
+        let change = price_change(
+            &history(
+                HistoryPeriod::Day,
+                vec![
+                    point(start - HOUR * 2, "1"),
+                    point(start - HOUR, "2"),
+                    point(start + HOUR * 2, "4"),
+                    point(now, "3"),
+                ],
+            ),
+            &price_usd,
+            now,
+        );
+        assert_eq!(change, Some(BigDecimal::from(50)));
+
+        let change = price_change(
+            &history(HistoryPeriod::Day, vec![point(start, "4")]),
+            &price_usd,
+            now,
+        );
+        assert_eq!(change, Some(BigDecimal::from(-25)));
+    }
+
+    #[test]
+    fn price_change_without_start_price() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let start = now - HistoryPeriod::Day.duration();
+        let price_usd = BigDecimal::from(3);
+        // The tolerance is 2.4 hours for 24h
+        let too_far = history(
+            HistoryPeriod::Day,
+            vec![point(start + HOUR * 3, "2"), point(now, "3")],
+        );
+        assert_eq!(price_change(&too_far, &price_usd, now), None);
+        let empty = history(HistoryPeriod::Day, Vec::new());
+        assert_eq!(price_change(&empty, &price_usd, now), None);
+        let zero = history(HistoryPeriod::Day, vec![point(start, "0")]);
+        assert_eq!(price_change(&zero, &price_usd, now), None);
+    }
+
+    #[test]
+    fn downsample_last_point_of_each_interval() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let start = now - HistoryPeriod::Hour.duration();
+        // 24 intervals of 150 seconds
+        let seconds = Duration::from_secs;
+        let points = [
+            point(start - seconds(10), "0"),
+            point(start, "1"),
+            point(start + seconds(100), "2"),
+            point(start + seconds(200), "3"),
+            point(start + seconds(3500), "4"),
+            point(now, "5"),
+        ];
+        let price_usd = BigDecimal::from(6);
+        let series = downsample(&points, HistoryPeriod::Hour, now, &price_usd);
+        // 4 is in the last interval, so it's replaced by the current price
+        assert_eq!(
+            series
+                .iter()
+                .map(|point| point.price_usd.to_string())
+                .collect::<Vec<_>>(),
+            ["2", "3", "6"]
+        );
+        assert_eq!(series[0].timestamp, start + seconds(100));
+        assert_eq!(series[2].timestamp, now);
+
+        let series = downsample(&[], HistoryPeriod::Hour, now, &price_usd);
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].price_usd, price_usd);
+        assert_eq!(series[0].timestamp, now);
+    }
+}
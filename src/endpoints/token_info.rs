@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use bigdecimal::RoundingMode;
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Reply;
+
+use crate::{
+    error::AgentError,
+    global_state::{get_token, is_spam_token, Token, TokenScore},
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{to_plain_string, TokenAmount},
+        formatting::{format_usd_amount, format_usd_cents, WRAP_NEAR},
+        response::{BalanceJson, ResponseFormat},
+    },
+};
+
+use super::token_prices::{resolve_token, ResolvedToken};
+
+pub const OPERATION: Operation = Operation {
+    path: "token-info",
+    operation_id: "getTokenInfo",
+    summary: "Get detailed information about a token",
+    description: "Returns the price, market cap, fully diluted valuation, supply, reputation, social links, and decimals of a token.",
+    response_description: "Detailed information about the token.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<TokenInfoInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TokenInfoInput {
+    /// The token: `NEAR`, a ticker, a name, or a token contract address. If there are several tokens with this name, the response lists them, ask the user which one they mean.
+    pub token: String,
+}
+
+/// JSON response of `/token-info`
+#[derive(Debug, Serialize)]
+pub struct TokenInfoJson {
+    pub contract_id: AccountId,
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u32,
+    pub price_usd: String,
+    /// Circulating supply times price, rounded to cents
+    pub market_cap_usd: String,
+    /// Total supply times price, rounded to cents
+    pub fully_diluted_valuation_usd: String,
+    pub total_supply: BalanceJson,
+    pub circulating_supply: BalanceJson,
+    pub circulating_supply_excluding_team: BalanceJson,
+    pub reputation: TokenScore,
+    pub is_spam: bool,
+    /// Social network name to link
+    pub socials: BTreeMap<String, String>,
+    /// Other names the token can be found by
+    pub slug: Vec<String>,
+}
+
+pub async fn get_token_info(
+    input: TokenInfoInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let TokenInfoInput { token } = input;
+    let contract_id = match resolve_token(&token).await? {
+        ResolvedToken::Near => WRAP_NEAR.parse().unwrap(),
+        ResolvedToken::Ft { contract_id, .. } => contract_id,
+    };
+    let Some(token) = get_token(&contract_id).await else {
+        return Err(AgentError::InvalidInput(format!(
+            "There's no information about {contract_id}, prices may not be loaded yet"
+        ))
+        .into());
+    };
+    let is_spam = is_spam_token(&contract_id).await;
+
+    let Token {
+        account_id,
+        price_usd,
+        metadata,
+        total_supply,
+        circulating_supply,
+        circulating_supply_excluding_team,
+        reputation,
+        socials,
+        slug,
+        ..
+    } = token;
+    let decimals = metadata.decimals;
+    let supply = |raw| TokenAmount::from_raw(raw, decimals);
+    let market_cap = supply(circulating_supply).usd_value(&price_usd);
+    let fully_diluted_valuation = supply(total_supply).usd_value(&price_usd);
+    let socials = socials.into_iter().collect::<BTreeMap<_, _>>();
+
+    if format == ResponseFormat::Json {
+        return Ok(warp::reply::json(&TokenInfoJson {
+            contract_id: account_id,
+            symbol: metadata.symbol,
+            name: metadata.name,
+            decimals,
+            price_usd: to_plain_string(&price_usd),
+            market_cap_usd: to_plain_string(&market_cap.with_scale_round(2, RoundingMode::HalfUp)),
+            fully_diluted_valuation_usd: to_plain_string(
+                &fully_diluted_valuation.with_scale_round(2, RoundingMode::HalfUp),
+            ),
+            total_supply: BalanceJson::new(total_supply, Some(decimals), Some(&price_usd)),
+            circulating_supply: BalanceJson::new(
+                circulating_supply,
+                Some(decimals),
+                Some(&price_usd),
+            ),
+            circulating_supply_excluding_team: BalanceJson::new(
+                circulating_supply_excluding_team,
+                Some(decimals),
+                Some(&price_usd),
+            ),
+            reputation,
+            is_spam,
+            socials,
+            slug,
+        })
+        .into_response());
+    }
+
+    let socials = if socials.is_empty() {
+        "none".to_string()
+    } else {
+        socials
+            .iter()
+            .map(|(network, link)| format!("\n- {network}: {link}"))
+            .collect()
+    };
+    let symbol = &metadata.symbol;
+    Ok(format!(
+        "{symbol} ({}), contract `{account_id}`
+Price: {}
+Market cap: {}
+Fully diluted valuation: {}
+Total supply: {}
+Circulating supply: {}
+Circulating supply excluding team: {}
+Decimals: {decimals}
+Reputation: {}
+Socials: {socials}",
+        metadata.name,
+        format_usd_amount(&price_usd),
+        format_usd_cents(&market_cap),
+        format_usd_cents(&fully_diluted_valuation),
+        supply(total_supply).format(symbol),
+        supply(circulating_supply).format(symbol),
+        supply(circulating_supply_excluding_team).format(symbol),
+        if is_spam {
+            "marked as spam, warn the user"
+        } else {
+            describe_reputation(reputation)
+        },
+    )
+    .into_response())
+}
+
+fn describe_reputation(reputation: TokenScore) -> &'static str {
+    match reputation {
+        TokenScore::Reputable => "reputable",
+        TokenScore::NotFake => "not fake, but not well known",
+        TokenScore::Unknown => "unknown, could be a scam, warn the user",
+        TokenScore::Spam => "spam, warn the user",
+    }
+}
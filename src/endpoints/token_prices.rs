@@ -1,14 +1,47 @@
-use serde::Deserialize;
+use std::{cmp::Reverse, collections::HashMap};
+
+use bigdecimal::BigDecimal;
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    global_state::Token,
-    utils::{formatting::format_usd_amount, rpc::get_cached_30s},
+    config::CONFIG,
+    error::AgentError,
+    global_state::{
+        find_reputable_token_by_symbol, get_token, is_spam_token, Token, TokenMetadataWithoutIcon,
+        TokenScore,
+    },
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{to_plain_string, TokenAmount},
+        formatting::format_usd_amount,
+        ft::resolve_ft_metadata,
+        ref_finance::get_total_reserves,
+        response::ResponseFormat,
+        rpc::get_cached_30s,
+    },
+};
+
+pub const OPERATION: Operation = Operation {
+    path: "token-prices",
+    operation_id: "getTokensPrices",
+    summary: "Get the prices of multiple tokens",
+    description: "Returns an array of strings representing the prices of the tokens.",
+    response_description: "An array of prices in USD and other information about tokens, the most reputable and liquid first (there may be more than 1 result for a search term if there is no exact match). Pass on warnings about possible scam tokens to the user.",
+    response: ResponseBody::TextList,
+    input_schema: input_schema::<TokenPricesInput>,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct TokenPricesInput {
+    /// Comma-separated list of token search terms (symbols, names, addresses, etc.)
     #[serde(deserialize_with = "from_comma_separated")]
+    #[schemars(with = "String")]
     tokens: Vec<String>,
+    /// Set to `true` only if the user explicitly asks for tokens marked as spam.
+    #[serde(default)]
+    include_spam: bool,
 }
 
 fn from_comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
@@ -19,29 +52,57 @@ where
     Ok(s.split(',').map(|s| s.to_string()).collect())
 }
 
+/// An element of the JSON response of `/token-prices`
+#[derive(Debug, Serialize)]
+pub struct TokenPriceJson {
+    /// The search query that matched this token
+    pub query: String,
+    pub contract_id: AccountId,
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u32,
+    pub price_usd: String,
+    pub reputation: TokenScore,
+    pub is_spam: bool,
+    /// Set if the token may be a scam
+    pub warning: Option<String>,
+}
+
+/// A token search result, see `find_tokens`
+pub struct SearchResult {
+    pub token: Token,
+    pub is_spam: bool,
+    /// Set for unknown tokens that use the symbol of a reputable token
+    pub warning: Option<String>,
+}
+
+impl SearchResult {
+    /// Spam list tokens rank as spam regardless of their score
+    fn reputation(&self) -> TokenScore {
+        if self.is_spam {
+            TokenScore::Spam
+        } else {
+            self.token.reputation
+        }
+    }
+}
+
 pub async fn get_token_prices(
     input: TokenPricesInput,
+    format: ResponseFormat,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let TokenPricesInput { tokens } = input;
+    let TokenPricesInput {
+        tokens,
+        include_spam,
+    } = input;
     let mut search_results = Vec::new();
     for token in tokens {
-        search_results.push(async {
+        search_results.push(async move {
             let token = token;
-            let results = search_tokens(&token).await;
-            let exact_matches = results
-                .iter()
-                .filter(|result| {
-                    result.metadata.symbol.to_lowercase().trim_matches('$')
-                        == token.to_lowercase().trim_matches('$')
-                        || result.metadata.name.to_lowercase().trim_matches('$')
-                            == token.to_lowercase().trim_matches('$')
-                })
-                .collect::<Vec<_>>();
-            if exact_matches.is_empty() {
-                results
-            } else {
-                exact_matches.into_iter().cloned().collect()
-            }
+            find_tokens(&token, include_spam)
+                .await
+                .into_iter()
+                .map(move |result| (token.clone(), result))
         });
     }
     let results = futures_util::future::join_all(search_results)
@@ -49,23 +110,265 @@ pub async fn get_token_prices(
         .into_iter()
         .flatten()
         .collect::<Vec<_>>();
+    if format == ResponseFormat::Json {
+        let response = results
+            .into_iter()
+            .map(|(query, result)| TokenPriceJson {
+                query,
+                reputation: result.reputation(),
+                is_spam: result.is_spam,
+                warning: result.warning,
+                contract_id: result.token.account_id,
+                symbol: result.token.metadata.symbol,
+                name: result.token.metadata.name,
+                decimals: result.token.metadata.decimals,
+                price_usd: to_plain_string(&result.token.price_usd),
+            })
+            .collect::<Vec<_>>();
+        return Ok(warp::reply::json(&response));
+    }
     let mut response = Vec::new();
-    for result in results {
-        response.push(format!(
+    for (_, result) in results {
+        let mut line = format!(
             "Ticker `{}`, Name `{}`, Contract `{}`, Price {}",
-            result.metadata.symbol,
-            result.metadata.name,
-            result.account_id,
-            format_usd_amount(result.price_usd.to_string().parse().unwrap_or_default())
-        ));
+            result.token.metadata.symbol,
+            result.token.metadata.name,
+            result.token.account_id,
+            format_usd_amount(&result.token.price_usd)
+        );
+        if result.is_spam {
+            line.push_str(", marked as spam");
+        }
+        if let Some(warning) = result.warning {
+            line.push_str(&format!(". Warning: {warning}"));
+        }
+        response.push(line);
     }
     Ok(warp::reply::json(&response))
 }
 
-async fn search_tokens(query: &str) -> Vec<Token> {
-    get_cached_30s(&format!(
-        "https://prices.intear.tech/token-search?q={query}"
+/// Tokens whose contract, symbol or name is `query`, or all search results
+/// if there are no exact matches. Best first: the exact contract match, then
+/// by reputation, then by liquidity on Ref Finance. Spam is only included if
+/// `include_spam` is set or its contract is `query`.
+pub async fn find_tokens(query: &str, include_spam: bool) -> Vec<SearchResult> {
+    let mut results = search_tokens(query).await;
+    let contract_match = parse_contract_address(query);
+    if let Some(contract_id) = &contract_match {
+        if !results.iter().any(|token| &token.account_id == contract_id) {
+            if let Some(token) = get_token(contract_id).await {
+                results.push(token);
+            }
+        }
+    }
+    let is_contract_match = |token: &Token| contract_match.as_ref() == Some(&token.account_id);
+
+    let mut ranked = Vec::new();
+    for token in results {
+        let is_spam =
+            token.reputation == TokenScore::Spam || is_spam_token(&token.account_id).await;
+        if is_spam && !include_spam && !is_contract_match(&token) {
+            continue;
+        }
+        let warning = imitation_warning(&token).await;
+        ranked.push(SearchResult {
+            token,
+            is_spam,
+            warning,
+        });
+    }
+
+    let is_exact_match = |result: &SearchResult| {
+        let query = query.to_lowercase();
+        let query = query.trim_matches('$');
+        is_contract_match(&result.token)
+            || result
+                .token
+                .metadata
+                .symbol
+                .to_lowercase()
+                .trim_matches('$')
+                == query
+            || result.token.metadata.name.to_lowercase().trim_matches('$') == query
+    };
+    if ranked.iter().any(is_exact_match) {
+        ranked.retain(is_exact_match);
+    }
+
+    if ranked.len() > 1 {
+        let reserves = get_total_reserves().await.unwrap_or_else(|err| {
+            log::warn!("Failed to get Ref Finance pools to rank tokens: {err:?}");
+            Default::default()
+        });
+        ranked.sort_by_cached_key(|result| {
+            Reverse((
+                is_contract_match(&result.token),
+                result.reputation(),
+                liquidity_usd(&result.token, &reserves),
+            ))
+        });
+    }
+    ranked
+}
+
+/// USD value of the token in Ref Finance pools
+fn liquidity_usd(token: &Token, reserves: &HashMap<AccountId, u128>) -> BigDecimal {
+    let reserve = reserves.get(&token.account_id).copied().unwrap_or_default();
+    TokenAmount::from_raw(reserve, token.metadata.decimals).usd_value(&token.price_usd)
+}
+
+/// Unknown tokens with the symbol of a reputable token are likely scams
+async fn imitation_warning(token: &Token) -> Option<String> {
+    if token.reputation != TokenScore::Unknown {
+        return None;
+    }
+    let reputable = find_reputable_token_by_symbol(&token.metadata.symbol).await?;
+    if reputable.account_id == token.account_id {
+        return None;
+    }
+    Some(format!(
+        "this is an unknown token with the same symbol as the reputable {} (contract `{}`), it may be a scam",
+        reputable.metadata.symbol, reputable.account_id
     ))
-    .await
-    .unwrap_or_default()
+}
+
+async fn search_tokens(query: &str) -> Vec<Token> {
+    let Some(url) = token_search_url(&CONFIG.prices_api_url, query) else {
+        return Vec::new();
+    };
+    get_cached_30s(&url).await.unwrap_or_default()
+}
+
+/// `query` is encoded, so that `&`, `#`, and spaces don't change the URL
+fn token_search_url(prices_api_url: &str, query: &str) -> Option<String> {
+    reqwest::Url::parse_with_params(&format!("{prices_api_url}/token-search"), [("q", query)])
+        .ok()
+        .map(String::from)
+}
+
+/// `query` as a contract address if it's written like one: with a `.` like
+/// `usdc.near`, or a 64-character implicit account. Tickers like `aurora` are
+/// often valid top-level account IDs too, but the user means the token.
+fn parse_contract_address(query: &str) -> Option<AccountId> {
+    let is_implicit = query.len() == 64
+        && query
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+    if !query.contains('.') && !is_implicit {
+        return None;
+    }
+    query.parse().ok()
+}
+
+/// Token that the user means, see `resolve_token`
+pub enum ResolvedToken {
+    Near,
+    Ft {
+        contract_id: AccountId,
+        metadata: TokenMetadataWithoutIcon,
+    },
+}
+
+/// Resolves `NEAR`, a contract address, or a ticker or name that matches
+/// exactly one token
+pub async fn resolve_token(query: &str) -> Result<ResolvedToken, AgentError> {
+    let query = query.trim();
+    if query.trim_start_matches('$').eq_ignore_ascii_case("near") {
+        return Ok(ResolvedToken::Near);
+    }
+    if let Some(contract_id) = parse_contract_address(query) {
+        if let Some(metadata) = resolve_ft_metadata(&contract_id).await {
+            return Ok(ResolvedToken::Ft {
+                contract_id,
+                metadata,
+            });
+        }
+    }
+    let mut tokens = find_tokens(query, false).await;
+    // Unlisted tokens on top-level accounts, only if no listed token matches
+    if tokens.is_empty() {
+        if let Ok(contract_id) = query.parse::<AccountId>() {
+            if let Some(metadata) = resolve_ft_metadata(&contract_id).await {
+                return Ok(ResolvedToken::Ft {
+                    contract_id,
+                    metadata,
+                });
+            }
+        }
+    }
+    // A reputable token is preferred over unknown ones with the same name
+    if let Some(best) = tokens.first().map(SearchResult::reputation) {
+        let best_count = tokens
+            .iter()
+            .filter(|token| token.reputation() == best)
+            .count();
+        if best_count == 1 {
+            tokens.truncate(1);
+        }
+    }
+    let mut tokens = tokens
+        .into_iter()
+        .map(|result| result.token)
+        .collect::<Vec<_>>();
+    match tokens.len() {
+        0 => Err(AgentError::InvalidInput(format!(
+            "Token `{query}` not found. Ask the user for the token contract address."
+        ))),
+        1 => {
+            let token = tokens.remove(0);
+            Ok(ResolvedToken::Ft {
+                contract_id: token.account_id,
+                metadata: token.metadata,
+            })
+        }
+        _ => Err(AgentError::InvalidInput(format!(
+            "`{query}` matches several tokens, ask the user which one they mean: {}",
+            tokens
+                .iter()
+                .map(|token| format!(
+                    "{} ({}, contract `{}`)",
+                    token.metadata.symbol, token.metadata.name, token.account_id
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contract_address() {
+        assert_eq!(
+            parse_contract_address("usdc.near"),
+            Some("usdc.near".parse().unwrap())
+        );
+        let implicit = "a".repeat(64);
+        assert_eq!(
+            parse_contract_address(&implicit),
+            Some(implicit.parse().unwrap())
+        );
+        // Bare tickers are valid account IDs, but not contract addresses
+        assert_eq!(parse_contract_address("aurora"), None);
+        assert_eq!(parse_contract_address("usdc"), None);
+        assert_eq!(parse_contract_address("USDC"), None);
+        assert_eq!(parse_contract_address("$near"), None);
+        assert_eq!(parse_contract_address(&"g".repeat(64)), None);
+        assert_eq!(parse_contract_address("not a.contract"), None);
+    }
+
+    #[test]
+    fn search_url_is_encoded() {
+        let api_url = "https://prices.intear.tech";
+        assert_eq!(
+            token_search_url(api_url, "usdc").as_deref(),
+            Some("https://prices.intear.tech/token-search?q=usdc")
+        );
+        assert_eq!(
+            token_search_url(api_url, "a&b=c #d").as_deref(),
+            Some("https://prices.intear.tech/token-search?q=a%26b%3Dc+%23d")
+        );
+    }
 }
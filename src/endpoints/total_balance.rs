@@ -1,74 +1,367 @@
+use crate::config::CONFIG;
+use crate::error::AgentError;
+use crate::manifest::{input_schema, Operation, ResponseBody};
 use crate::utils::{
-    formatting::{format_near_amount, format_tokens},
+    amount::to_plain_string,
+    amount::{NearAmount, TokenAmount},
+    formatting::{format_near_amount, format_tokens, format_usd_cents},
+    ft::resolve_ft_metadata,
+    response::{BalanceJson, ResponseFormat},
     rpc::{get_cached_30s, view_account_cached_30s},
 };
 
+use bigdecimal::{BigDecimal, RoundingMode, Zero};
+use futures_util::StreamExt;
 use itertools::Itertools;
 use near_primitives::types::{AccountId, BlockHeight};
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Reply;
 
-use crate::global_state::{get_ft_metadata, get_ft_price, is_spam_token};
+use crate::global_state::{
+    get_ft_metadata, get_ft_price, get_near_price, get_tokens_age, is_spam_token,
+    TokenMetadataWithoutIcon,
+};
+
+use super::staking::{
+    format_staking_info, get_delegated_validators, staking_positions_json, StakingData,
+    StakingPositionJson, NOT_STAKING_THRESHOLD,
+};
 
-use super::staking::format_staking_info;
+/// Metadata of tokens that aren't in the token list is loaded from their
+/// contracts, this many at a time, so that an account with hundreds of spam
+/// tokens doesn't send hundreds of requests to the RPC at once
+const MAX_CONCURRENT_METADATA_REQUESTS: usize = 8;
 
-#[derive(Debug, Deserialize)]
+pub const OPERATION: Operation = Operation {
+    path: "total-balance",
+    operation_id: "getTotalBalance",
+    summary: "Get the total balance of an account",
+    description: "Returns the total balance of an account as a string, including its net worth in USD and the share of each asset. Use the net worth from the response instead of adding up the balances.",
+    response_description: "The total balance, separate token balances, and net worth of the user.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<WrappedAccountId>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct WrappedAccountId {
+    /// The account ID to retrieve the balance for.
+    #[schemars(with = "String")]
+    pub account_id: AccountId,
+}
+
+/// JSON response of `/total-balance`
+#[derive(Debug, Serialize)]
+pub struct TotalBalanceResponse {
     pub account_id: AccountId,
+    pub near: BalanceJson,
+    /// `null` if staking information couldn't be loaded
+    pub staking: Option<Vec<StakingPositionJson>>,
+    /// Sorted by USD value, spam tokens are excluded
+    pub tokens: Vec<TokenBalanceJson>,
+    /// `false` if the token list isn't loaded yet, so there are no prices or
+    /// metadata
+    pub prices_available: bool,
+    /// `null` if prices are unavailable
+    pub portfolio: Option<PortfolioJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortfolioJson {
+    pub net_worth_usd: String,
+    /// `false` if staking information couldn't be loaded, so staked NEAR is
+    /// not counted
+    pub includes_staking: bool,
+    /// Sorted by USD value, assets worth $0 are excluded
+    pub allocation: Vec<AllocationJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AllocationJson {
+    pub asset: String,
+    pub usd_value: String,
+    /// Share of the net worth, rounded to 2 decimal places
+    pub percent: String,
+}
+
+/// USD value of everything the account holds
+struct Portfolio {
+    net_worth: BigDecimal,
+    includes_staking: bool,
+    /// Asset name and its USD value, largest first
+    assets: Vec<(String, BigDecimal)>,
+}
+
+impl Portfolio {
+    fn percent(&self, value: &BigDecimal) -> String {
+        if self.net_worth.is_zero() {
+            return "0".to_string();
+        }
+        to_plain_string(
+            &(value * BigDecimal::from(100) / &self.net_worth)
+                .with_scale_round(2, RoundingMode::HalfUp),
+        )
+    }
+
+    fn to_json(&self) -> PortfolioJson {
+        PortfolioJson {
+            net_worth_usd: to_plain_string(
+                &self.net_worth.with_scale_round(2, RoundingMode::HalfUp),
+            ),
+            includes_staking: self.includes_staking,
+            allocation: self
+                .assets
+                .iter()
+                .map(|(asset, value)| AllocationJson {
+                    asset: asset.clone(),
+                    usd_value: to_plain_string(&value.with_scale_round(2, RoundingMode::HalfUp)),
+                    percent: self.percent(value),
+                })
+                .collect(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let mut text = format!("Net worth: {}", format_usd_cents(&self.net_worth));
+        if !self.includes_staking {
+            text.push_str(" (not including staked NEAR, which couldn't be loaded)");
+        }
+        text.push_str("\nAllocation:");
+        for (asset, value) in &self.assets {
+            text.push_str(&format!(
+                "\n- {asset}: {}% ({})",
+                self.percent(value),
+                format_usd_cents(value)
+            ));
+        }
+        text
+    }
+}
+
+/// Dust amounts are skipped, like in /staking
+fn sum_without_dust(amounts: impl Iterator<Item = NearAmount>) -> NearAmount {
+    amounts
+        .filter(|amount| *amount > NOT_STAKING_THRESHOLD)
+        .sum()
+}
+
+/// A token the account holds
+struct OwnedToken {
+    contract_id: AccountId,
+    balance: u128,
+    /// `None` if the contract has no valid metadata
+    metadata: Option<TokenMetadataWithoutIcon>,
+    /// The metadata is from the token list. Otherwise it's self-reported by
+    /// the contract, so a spam token can pose as another token.
+    is_verified: bool,
+    /// Zero if there's no price
+    usd_value: BigDecimal,
+}
+
+/// `None` if prices are unavailable
+async fn get_portfolio(
+    near_balance: NearAmount,
+    staking_data: &Result<Vec<StakingData>, AgentError>,
+    tokens: &[OwnedToken],
+) -> Option<Portfolio> {
+    get_tokens_age().await?;
+    let near_price = get_near_price().await?;
+    let near_value = |amount: NearAmount| amount.as_token_amount().usd_value(&near_price);
+
+    let mut assets = vec![("NEAR".to_string(), near_value(near_balance))];
+    if let Ok(staking_data) = staking_data {
+        assets.push((
+            "Staked NEAR".to_string(),
+            near_value(sum_without_dust(
+                staking_data.iter().map(|d| d.staked_amount),
+            )),
+        ));
+        let (withdrawable, unstaking): (Vec<_>, Vec<_>) = staking_data
+            .iter()
+            .partition(|d| d.is_unstaked_balance_available);
+        assets.push((
+            "Unstaking NEAR".to_string(),
+            near_value(sum_without_dust(
+                unstaking.into_iter().map(|d| d.unstaked_amount),
+            )),
+        ));
+        assets.push((
+            "Withdrawable NEAR".to_string(),
+            near_value(sum_without_dust(
+                withdrawable.into_iter().map(|d| d.unstaked_amount),
+            )),
+        ));
+    }
+    for token in tokens {
+        let symbol = match &token.metadata {
+            Some(metadata) if token.is_verified => metadata.symbol.clone(),
+            _ => token.contract_id.to_string(),
+        };
+        assets.push((symbol, token.usd_value.clone()));
+    }
+
+    let net_worth = assets.iter().map(|(_, value)| value).sum();
+    Some(Portfolio {
+        net_worth,
+        includes_staking: staking_data.is_ok(),
+        assets: assets
+            .into_iter()
+            .filter(|(_, value)| !value.is_zero())
+            .sorted_by(|(_, value_1), (_, value_2)| value_2.cmp(value_1))
+            .collect(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenBalanceJson {
+    pub contract_id: AccountId,
+    pub symbol: Option<String>,
+    pub name: Option<String>,
+    /// `false` if the token isn't in the token list, so `symbol` and `name`
+    /// are set by the contract and may imitate another token
+    pub is_verified: bool,
+    #[serde(flatten)]
+    pub balance: BalanceJson,
 }
 
 pub async fn get_total_balance(
     input: WrappedAccountId,
-) -> Result<impl warp::Reply, warp::Rejection> {
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
     let WrappedAccountId { account_id } = input;
     let near_balance = view_account_cached_30s(account_id.clone())
         .await
-        .map_err(|e| {
-            log::warn!("Failed to get NEAR balance of {account_id}: {e:?}");
-            warp::reject::reject()
-        })?
+        .map_err(AgentError::rpc)?
         .amount;
 
-    let staked_near = format_staking_info(&account_id).await;
+    let staking_data = get_delegated_validators(&account_id).await;
 
-    let tokens = get_all_fts_owned(&account_id).await;
-    let tokens = {
-        let mut tokens_with_price = Vec::new();
-        for (token_id, balance) in tokens {
-            if is_spam_token(&token_id).await {
-                continue;
-            }
-            if let Some(meta) = get_ft_metadata(&token_id).await {
-                let price = get_ft_price(&token_id).await.unwrap_or_default();
-                let balance_human_readable = balance as f64 / 10f64.powi(meta.decimals as i32);
-                tokens_with_price.push((token_id, balance, balance_human_readable * price));
-            }
+    // Without the token list, there's no metadata to filter by, so show
+    // everything with raw amounts
+    let tokens_loaded = get_tokens_age().await.is_some();
+    let mut tokens = Vec::new();
+    for (token_id, balance) in get_all_fts_owned(&account_id).await {
+        if balance > 0 && !is_spam_token(&token_id).await {
+            tokens.push((token_id, balance));
         }
-        tokens_with_price
-    };
-    let tokens = tokens
+    }
+    // Metadata of unlisted tokens is loaded from their contracts, so it's
+    // loaded for several tokens at once
+    let mut metadata = futures_util::stream::iter(tokens.iter().cloned().enumerate())
+        .map(|(i, (token_id, _))| async move {
+            let metadata = match get_ft_metadata(&token_id).await {
+                Some(metadata) => Some((metadata, true)),
+                None => resolve_ft_metadata(&token_id)
+                    .await
+                    .map(|metadata| (metadata, false)),
+            };
+            (i, metadata)
+        })
+        .buffer_unordered(MAX_CONCURRENT_METADATA_REQUESTS)
+        .collect::<Vec<_>>()
+        .await;
+    metadata.sort_by_key(|(i, _)| *i);
+    let mut owned_tokens = Vec::new();
+    for ((contract_id, balance), (_, metadata)) in tokens.into_iter().zip(metadata) {
+        let (metadata, is_verified) = match metadata {
+            Some((metadata, is_verified)) => (Some(metadata), is_verified),
+            None => (None, false),
+        };
+        // Tokens without metadata are only shown with raw amounts if there's
+        // no token list
+        let usd_value = match &metadata {
+            Some(metadata) => {
+                let price = get_ft_price(&contract_id).await.unwrap_or_default();
+                TokenAmount::from_raw(balance, metadata.decimals).usd_value(&price)
+            }
+            None if !tokens_loaded => BigDecimal::zero(),
+            None => continue,
+        };
+        owned_tokens.push(OwnedToken {
+            contract_id,
+            balance,
+            metadata,
+            is_verified,
+            usd_value,
+        });
+    }
+    let tokens = owned_tokens
         .into_iter()
-        .filter(|(_, balance, _)| *balance > 0)
-        .sorted_by(|(_, _, balance_1), (_, _, balance_2)| balance_2.partial_cmp(balance_1).unwrap())
+        .sorted_by(|token_1, token_2| token_2.usd_value.cmp(&token_1.usd_value))
         .collect::<Vec<_>>();
+    let portfolio = get_portfolio(near_balance, &staking_data, &tokens).await;
+
+    if format == ResponseFormat::Json {
+        let mut tokens_json = Vec::new();
+        for token in tokens {
+            let OwnedToken {
+                contract_id,
+                balance,
+                metadata,
+                is_verified,
+                ..
+            } = token;
+            tokens_json.push(TokenBalanceJson {
+                balance: BalanceJson::new(
+                    balance,
+                    metadata.as_ref().map(|meta| meta.decimals),
+                    get_ft_price(&contract_id).await.as_ref(),
+                ),
+                symbol: metadata.as_ref().map(|meta| meta.symbol.clone()),
+                name: metadata.map(|meta| meta.name),
+                is_verified,
+                contract_id,
+            });
+        }
+        return Ok(warp::reply::json(&TotalBalanceResponse {
+            near: BalanceJson::near(near_balance, get_near_price().await.as_ref()),
+            staking: staking_positions_json(&account_id, &staking_data).await,
+            tokens: tokens_json,
+            prices_available: tokens_loaded,
+            portfolio: portfolio.as_ref().map(Portfolio::to_json),
+            account_id,
+        })
+        .into_response());
+    }
+
+    let staked_near = format_staking_info(&account_id, &staking_data).await;
     let mut tokens_balance = String::new();
-    for (ref token_id, balance, _) in tokens {
+    for token in &tokens {
         tokens_balance.push_str(&format!(
-            "{token_id} {}\n",
-            format_tokens(balance, token_id).await,
+            "{} {}{}\n",
+            token.contract_id,
+            format_tokens(token.balance, &token.contract_id, token.metadata.as_ref()).await,
+            if token.metadata.is_some() && !token.is_verified {
+                " [unverified token, its symbol is set by the contract and may imitate another token]"
+            } else {
+                ""
+            },
         ));
     }
 
+    let portfolio = match portfolio {
+        Some(portfolio) => format!("\n{}\n", portfolio.describe()),
+        None => "".to_string(),
+    };
+
+    let note = if tokens_loaded {
+        ""
+    } else {
+        "Token prices are temporarily unavailable, so USD values are not shown. Amounts of tokens without metadata are raw, without decimals.\n"
+    };
+
     Ok(format!(
         "
-NEAR balance: {}
+{note}NEAR balance: {}
 
 Staked NEAR: {staked_near}
 
 Tokens:
-{tokens_balance}
+{tokens_balance}{portfolio}
         ",
         format_near_amount(near_balance).await,
-    ))
+    )
+    .into_response())
 }
 
 async fn get_all_fts_owned(account_id: &AccountId) -> Vec<(AccountId, u128)> {
@@ -88,7 +381,7 @@ async fn get_all_fts_owned(account_id: &AccountId) -> Vec<(AccountId, u128)> {
         balance: String,
     }
 
-    let url = format!("https://api.fastnear.com/v1/account/{account_id}/ft");
+    let url = format!("{}/v1/account/{account_id}/ft", CONFIG.fastnear_api_url);
     match get_cached_30s::<Response>(&url).await {
         Ok(response) => response
             .tokens
@@ -0,0 +1,128 @@
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{HumanAmount, NearAmount, TokenAmount},
+        ft::{ft_balance_of, storage_deposit_needed},
+        response::ResponseFormat,
+        rpc::view_account_cached_30s,
+        transaction::{FunctionCallAction, Transaction, TransactionBatch, TransactionResponse},
+    },
+};
+
+use super::token_prices::{resolve_token, ResolvedToken};
+
+pub const OPERATION: Operation = Operation {
+    path: "transfer",
+    operation_id: "transfer",
+    summary: "Generate a transaction that sends NEAR or a fungible token to another account",
+    description: "Returns a transaction that sends NEAR or a fungible token to another account. If the receiver is not registered on the token contract, the transaction registers them first.",
+    response_description: "The transaction that sends the tokens.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<TransferInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TransferInput {
+    /// The account ID of the user who is sending the tokens.
+    #[schemars(with = "String")]
+    pub sender_id: AccountId,
+    /// The account ID to send the tokens to. Ask the user for it, DON'T guess.
+    pub receiver_id: String,
+    /// The token to send: `NEAR`, a ticker, a name, or a token contract address. If there are several tokens with this name, the response lists them, ask the user which one they mean.
+    pub token: String,
+    /// The amount of tokens to send, like `1.5`, or `all` to send the whole balance of a fungible token.
+    #[schemars(with = "String")]
+    pub amount: HumanAmount,
+}
+
+pub async fn create_transfer_transaction(
+    input: TransferInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(transfer(input).await?.into_reply(format))
+}
+
+async fn transfer(input: TransferInput) -> Result<TransactionResponse, AgentError> {
+    let TransferInput {
+        sender_id,
+        receiver_id,
+        token,
+        amount,
+    } = input;
+    let Ok(receiver_id) = receiver_id.parse::<AccountId>() else {
+        return Err(AgentError::InvalidAccount(receiver_id));
+    };
+    if !receiver_id.get_account_type().is_implicit() {
+        // Transfers to named accounts that don't exist fail
+        view_account_cached_30s(receiver_id.clone())
+            .await
+            .map_err(AgentError::rpc)?;
+    }
+
+    match resolve_token(&token).await? {
+        ResolvedToken::Near => {
+            let HumanAmount::Exact(amount) = amount else {
+                return Ok(TransactionResponse::message(
+                    "Sending all NEAR is not supported, because some NEAR is needed for gas and storage. Ask the user to specify an amount.",
+                ));
+            };
+            let amount = NearAmount::parse_human(&amount)?;
+            Ok(TransactionResponse::new(
+                TransactionBatch::single(Transaction::new(receiver_id).transfer(amount)),
+                "",
+            ))
+        }
+        ResolvedToken::Ft {
+            contract_id,
+            metadata,
+        } => {
+            let amount = match amount {
+                HumanAmount::All => {
+                    let balance = ft_balance_of(&contract_id, &sender_id)
+                        .await
+                        .map_err(AgentError::rpc)?;
+                    if balance == 0 {
+                        return Ok(TransactionResponse::message(format!(
+                            "{sender_id} doesn't have any {}.",
+                            metadata.symbol
+                        )));
+                    }
+                    TokenAmount::from_raw(balance, metadata.decimals)
+                }
+                HumanAmount::Exact(amount) => {
+                    TokenAmount::parse_human(&amount, metadata.decimals, &metadata.symbol)?
+                }
+            };
+
+            let mut transaction = Transaction::new(contract_id.clone());
+            let mut message = String::new();
+            if let Some(deposit) = storage_deposit_needed(&contract_id, &receiver_id)
+                .await
+                .map_err(AgentError::rpc)?
+            {
+                transaction = transaction
+                    .function_call(FunctionCallAction::storage_deposit(&receiver_id, deposit));
+                message = format!(
+                    "After that, let the user know that {receiver_id} wasn't registered on {contract_id}, so the transaction also pays {deposit} for their storage.",
+                );
+            }
+            let transaction = transaction.function_call(FunctionCallAction::new(
+                "ft_transfer",
+                serde_json::json!({
+                    "receiver_id": receiver_id,
+                    "amount": amount.raw().to_string(),
+                }),
+                NearAmount::from_yocto(1),
+            ));
+            Ok(TransactionResponse::new(
+                TransactionBatch::single(transaction),
+                message,
+            ))
+        }
+    }
+}
@@ -1,157 +1,187 @@
-use bigdecimal::{BigDecimal, FromPrimitive, Zero};
+use std::time::SystemTime;
+
 use near_primitives::types::AccountId;
+use schemars::JsonSchema;
 use serde::Deserialize;
 
-use super::staking::{get_delegated_validators, NOT_STAKING_THRESHOLD};
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{HumanAmount, NearAmount},
+        formatting::format_duration,
+        response::ResponseFormat,
+        transaction::{FunctionCallAction, Transaction, TransactionBatch, TransactionResponse},
+        validators::get_epoch_progress,
+    },
+};
+
+use super::{
+    resolve_pool::{resolve_pool, ResolvedPool},
+    staking::{
+        describe_unstaking, get_delegated_validators, get_unstaking_progress, StakingData,
+        NOT_STAKING_THRESHOLD,
+    },
+};
 
-#[derive(Debug, Deserialize)]
+pub const OPERATION: Operation = Operation {
+    path: "unstake",
+    operation_id: "unstake",
+    summary: "Generate a transaction that unstakes NEAR tokens on a given pool",
+    description: "Returns a transaction that unstakes NEAR tokens on a given pool.",
+    response_description: "The transaction that unstakes NEAR tokens on a given pool.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<UnstakeInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct UnstakeInput {
+    /// The account ID of the user who is unstaking.
+    #[schemars(with = "String")]
     pub account_id: AccountId,
-    pub pool_account_id: Option<AccountId>,
-    pub amount: Option<BigDecimal>,
+    /// The account ID of the pool to unstake from, or its name like `intear`, which is resolved to an account ID. If not provided, will unstake from all pools until the amount is unstaked. If amount is not provided, will unstake from all pools.
+    pub pool_account_id: Option<String>,
+    /// The amount of NEAR tokens to unstake, like `1.5`. If not provided or `all`, will unstake all staked tokens.
+    #[schemars(with = "Option<String>")]
+    pub amount: Option<HumanAmount>,
 }
 
 pub async fn create_unstake_transaction(
     input: UnstakeInput,
-) -> Result<impl warp::Reply, warp::Rejection> {
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(unstake(input).await?.into_reply(format))
+}
+
+async fn unstake(input: UnstakeInput) -> Result<TransactionResponse, AgentError> {
     let UnstakeInput {
         account_id,
         pool_account_id,
         amount,
     } = input;
-    let staking_data = get_delegated_validators(&account_id).await.map_err(|e| {
-        log::warn!("Failed to get staking data of {account_id}: {e:?}");
-        warp::reject::reject()
-    })?;
+    // `all` is the same as not specifying an amount
+    let amount = match amount {
+        Some(HumanAmount::Exact(amount)) => Some(NearAmount::parse_human(&amount)?),
+        Some(HumanAmount::All) | None => None,
+    };
+    let pool_account_id = match pool_account_id {
+        Some(pool_account_id) => match resolve_pool(&pool_account_id, Some(&account_id)).await? {
+            ResolvedPool::Pool(pool_account_id) => Some(pool_account_id),
+            ResolvedPool::Ambiguous(message) => return Ok(TransactionResponse::message(message)),
+        },
+        None => None,
+    };
+    let staking_data = get_delegated_validators(&account_id).await?;
     if let Some(pool_account_id) = pool_account_id {
         let pool_data = staking_data.iter().find(|d| d.pool_id == pool_account_id);
         let Some(pool_data) = pool_data else {
-            return Ok("You are not staking in this pool.".to_string());
+            return Ok(TransactionResponse::message(
+                "You are not staking in this pool.",
+            ));
         };
+        let staked_amount = pool_data.staked_amount;
+        let unstaked_amount = pool_data.unstaked_amount;
         if let Some(amount) = amount {
-            let yocto = amount * BigDecimal::from_u128(10u128.pow(24)).unwrap();
-            if BigDecimal::from_u128(pool_data.unstaked_amount).unwrap() >= yocto {
-                return Ok(format!(
-                    r#"
-[
-    {{
-        "methodName": "withdraw",
-        "args": "{{\"amount\": \"{yocto}\"}}",
-        "gas": "50000000000000",
-        "deposit": "0",
-        "contractName": "{pool_account_id}"
-    }}
-]
-
-Use this data to call `generate-transaction` tool to generate a transaction.
-    "#
+            if unstaked_amount >= amount {
+                if !pool_data.is_unstaked_balance_available {
+                    return Err(still_unstaking(pool_data).await);
+                }
+                return Ok(TransactionResponse::new(
+                    TransactionBatch::single(Transaction::new(pool_account_id).function_call(
+                        FunctionCallAction::new(
+                            "withdraw",
+                            serde_json::json!({ "amount": amount }),
+                            NearAmount::ZERO,
+                        ),
+                    )),
+                    "",
                 ));
             }
-            if BigDecimal::from_u128(pool_data.staked_amount).unwrap() < yocto {
-                return Ok(format!(
-                    "You are only staking {} NEAR in this pool. {} NEAR is available for withdrawal.",
-                    BigDecimal::from_u128(pool_data.staked_amount).unwrap()
-                        / BigDecimal::from_u128(10u128.pow(24)).unwrap(),
-                    BigDecimal::from_u128(pool_data.unstaked_amount).unwrap()
-                        / BigDecimal::from_u128(10u128.pow(24)).unwrap()
-                ));
+            if staked_amount < amount {
+                return Ok(TransactionResponse::message(format!(
+                    "You are only staking {staked_amount} in this pool. {unstaked_amount} is {}.",
+                    if pool_data.is_unstaked_balance_available {
+                        "available for withdrawal"
+                    } else {
+                        "still unstaking"
+                    }
+                )));
             }
-            Ok(format!(
-                r#"
-    let yocto = amount * BigDecimal::from_u128(10u128.pow(24)).unwrap();
-    Ok(format!(r#"
-[
-    {{
-      "methodName": "unstake",
-      "args": "{{\"amount\": \"{yocto}\"}}",
-      "gas": "50000000000000",
-      "deposit": "0",
-      "contractName": "{pool_account_id}"
-    }}
-]
-
-Use this data to call `generate-transaction` tool to generate a transaction. After that, let the user know that unstaking takes 2-3 days on average. Don't forget to use the correct syntax for the `generate-transaction` tool, the provided JSON is only a part of the arguments.
-    "#
+            Ok(TransactionResponse::new(
+                TransactionBatch::single(Transaction::new(pool_account_id).function_call(
+                    FunctionCallAction::new(
+                        "unstake",
+                        serde_json::json!({ "amount": amount }),
+                        NearAmount::ZERO,
+                    ),
+                )),
+                unstake_instructions().await,
             ))
         } else {
-            if pool_data.unstaked_amount != 0 {
-                return Ok(format!(
-                    r#"
-[
-    {{
-        "methodName": "withdraw_all",
-        "args": "{{}}",
-        "gas": "50000000000000",
-        "deposit": "0",
-        "contractName": "{pool_account_id}"
-    }}
-]
-
-Use this data to call `generate-transaction` tool to generate a transaction. After that, let the user know that {} NEAR has been withdrawn from the pool{} Don't forget to use the correct syntax for the `generate-transaction` tool, the provided JSON is only a part of the arguments.
-    "#,
-                    BigDecimal::from_u128(pool_data.unstaked_amount).unwrap()
-                        / BigDecimal::from_u128(10u128.pow(24)).unwrap(),
+            if !pool_data.unstaked_amount.is_zero() && pool_data.is_unstaked_balance_available {
+                let instructions = format!(
+                    "After that, let the user know that {unstaked_amount} has been withdrawn from the pool{}",
                     if pool_data.staked_amount <= NOT_STAKING_THRESHOLD {
                         ".".to_string()
                     } else {
                         format!(
-                            ", and {} NEAR is available for unstake. To unstake, repeat the same tool",
-                            BigDecimal::from_u128(pool_data.staked_amount).unwrap()
-                                / BigDecimal::from_u128(10u128.pow(24)).unwrap()
+                            ", and {staked_amount} is available for unstake. To unstake, repeat the same tool",
                         )
                     }
+                );
+                return Ok(TransactionResponse::new(
+                    TransactionBatch::single(Transaction::new(pool_account_id).function_call(
+                        FunctionCallAction::new(
+                            "withdraw_all",
+                            serde_json::json!({}),
+                            NearAmount::ZERO,
+                        ),
+                    )),
+                    instructions,
                 ));
             }
             if pool_data.staked_amount <= NOT_STAKING_THRESHOLD {
-                // TODO try withdraw instead of unstake
-                return Ok("You are not staking in this pool.".to_string());
+                if pool_data.unstaked_amount > NOT_STAKING_THRESHOLD {
+                    return Err(still_unstaking(pool_data).await);
+                }
+                return Ok(TransactionResponse::message(
+                    "You are not staking in this pool.",
+                ));
             }
-            Ok(format!(
-                r#"
-    let yocto = amount * BigDecimal::from_u128(10u128.pow(24)).unwrap();
-    Ok(format!(r#"
-[
-    {{
-      "methodName": "unstake_all",
-      "args": "{{}}",
-      "gas": "50000000000000",
-      "deposit": "0",
-      "contractName": "{pool_account_id}"
-    }}
-]
-
-Use this data to call `generate-transaction` tool to generate a transaction. After that, let the user know that unstaking takes 2-3 days on average. Don't forget to use the correct syntax for the `generate-transaction` tool, the provided JSON is only a part of the arguments.
-    "#
+            Ok(TransactionResponse::new(
+                TransactionBatch::single(Transaction::new(pool_account_id).function_call(
+                    FunctionCallAction::new("unstake_all", serde_json::json!({}), NearAmount::ZERO),
+                )),
+                unstake_instructions().await,
             ))
         }
     } else {
-        let mut data = Vec::new();
-        let mut max_unstake_amount = amount.clone();
+        let mut batch = TransactionBatch::default();
+        let mut max_unstake_amount = amount;
 
-        // Withdraw unstaked
+        // Withdraw unstaked, unless it's still unstaking
         for pool_data in staking_data.iter() {
+            if pool_data.unstaked_amount <= NOT_STAKING_THRESHOLD
+                || !pool_data.is_unstaked_balance_available
+            {
+                continue;
+            }
             let to_withdraw = if let Some(max_unstake_amount) = max_unstake_amount.as_mut() {
-                if *max_unstake_amount == BigDecimal::zero() {
+                if max_unstake_amount.is_zero() {
                     break;
                 }
-                let to_withdraw = max_unstake_amount
-                    .clone()
-                    .min(BigDecimal::from_u128(pool_data.unstaked_amount).unwrap());
-                *max_unstake_amount -= to_withdraw.clone();
+                let to_withdraw = (*max_unstake_amount).min(pool_data.unstaked_amount);
+                *max_unstake_amount = max_unstake_amount.saturating_sub(to_withdraw);
                 to_withdraw
             } else {
-                BigDecimal::from_u128(pool_data.unstaked_amount).unwrap()
+                pool_data.unstaked_amount
             };
-            data.push(format!(
-                r#"
-    {{
-        "methodName": "withdraw",
-        "args": "{{\"amount\": \"{to_withdraw}\"}}",
-        "gas": "50000000000000",
-        "deposit": "0",
-        "contractName": "{}"
-    }}"#,
-                pool_data.pool_id
+            batch.push(Transaction::new(pool_data.pool_id.clone()).function_call(
+                FunctionCallAction::new(
+                    "withdraw",
+                    serde_json::json!({ "amount": to_withdraw }),
+                    NearAmount::ZERO,
+                ),
             ));
         }
 
@@ -161,35 +191,72 @@ Use this data to call `generate-transaction` tool to generate a transaction. Aft
                 continue;
             }
             let to_unstake = if let Some(max_unstake_amount) = max_unstake_amount.as_mut() {
-                if *max_unstake_amount == BigDecimal::zero() {
+                if max_unstake_amount.is_zero() {
                     break;
                 }
-                let to_unstake = max_unstake_amount
-                    .clone()
-                    .min(BigDecimal::from_u128(pool_data.staked_amount).unwrap());
-                *max_unstake_amount -= to_unstake.clone();
+                let to_unstake = (*max_unstake_amount).min(pool_data.staked_amount);
+                *max_unstake_amount = max_unstake_amount.saturating_sub(to_unstake);
                 to_unstake
             } else {
-                BigDecimal::from_u128(pool_data.staked_amount).unwrap()
+                pool_data.staked_amount
             };
-            data.push(format!(
-                r#"
-    {{
-        "methodName": "unstake",
-        "args": "{{\"amount\": \"{to_unstake}\"}}",
-        "gas": "50000000000000",
-        "deposit": "0",
-        "contractName": "{}"
-    }}"#,
-                pool_data.pool_id
+            batch.push(Transaction::new(pool_data.pool_id.clone()).function_call(
+                FunctionCallAction::new(
+                    "unstake",
+                    serde_json::json!({ "amount": to_unstake }),
+                    NearAmount::ZERO,
+                ),
             ));
         }
-        if data.is_empty() {
-            return Ok("You are not staking in any pool.".to_string());
+        if batch.is_empty() {
+            if let Some(pool_data) = staking_data
+                .iter()
+                .find(|d| d.unstaked_amount > NOT_STAKING_THRESHOLD)
+            {
+                return Err(still_unstaking(pool_data).await);
+            }
+            return Ok(TransactionResponse::message(
+                "You are not staking in any pool.",
+            ));
         }
-        Ok(format!(
-            r#"Use `generate-transaction` with {{"transactions":[{}]}}"#,
-            data.join(",")
+        Ok(TransactionResponse::new(
+            batch,
+            unstake_instructions().await,
         ))
     }
 }
+
+/// Withdrawing NEAR that is still unstaking fails on-chain, so it's
+/// explained instead
+async fn still_unstaking(pool_data: &StakingData) -> AgentError {
+    let progress = get_unstaking_progress(std::slice::from_ref(pool_data)).await;
+    AgentError::InvalidInput(format!(
+        "{} in {} can't be withdrawn yet. {}.",
+        pool_data.unstaked_amount,
+        pool_data.pool_id,
+        describe_unstaking(pool_data, progress.as_ref())
+    ))
+}
+
+/// When the NEAR unstaked now can be withdrawn
+async fn unstake_instructions() -> String {
+    match get_epoch_progress().await {
+        Ok(progress) => {
+            let epoch_height = progress.unstake_available_epoch(None);
+            format!(
+                "After that, let the user know that the unstaked NEAR can be withdrawn in epoch {epoch_height} (the current epoch is {}), in about {}.",
+                progress.epoch_height,
+                format_duration(
+                    progress
+                        .epoch_start_time(epoch_height)
+                        .duration_since(SystemTime::now())
+                        .unwrap_or_default()
+                ),
+            )
+        }
+        Err(err) => {
+            log::warn!("Failed to get epoch progress: {err:?}");
+            "After that, let the user know that unstaking takes 2-3 days on average.".to_string()
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{HumanAmount, NearAmount, TokenAmount},
+        formatting::{NEAR_DECIMALS, WRAP_NEAR},
+        ft::ft_balance_of,
+        response::ResponseFormat,
+        transaction::{FunctionCallAction, Transaction, TransactionBatch, TransactionResponse},
+    },
+};
+
+pub const OPERATION: Operation = Operation {
+    path: "unwrap",
+    operation_id: "unwrap",
+    summary: "Generate a transaction that unwraps wNEAR into NEAR",
+    description: "Returns a transaction that converts wNEAR (wrap.near) to NEAR 1:1.",
+    response_description: "The transaction that unwraps wNEAR.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<UnwrapInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct UnwrapInput {
+    /// The account ID of the user who is unwrapping wNEAR.
+    #[schemars(with = "String")]
+    pub account_id: AccountId,
+    /// The amount of wNEAR to unwrap, like `1.5`, or `all`.
+    #[schemars(with = "String")]
+    pub amount: HumanAmount,
+}
+
+pub async fn create_unwrap_transaction(
+    input: UnwrapInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(unwrap(input).await?.into_reply(format))
+}
+
+async fn unwrap(input: UnwrapInput) -> Result<TransactionResponse, AgentError> {
+    let UnwrapInput { account_id, amount } = input;
+    let wrap_near: AccountId = WRAP_NEAR.parse().unwrap();
+    let balance = NearAmount::from_yocto(
+        ft_balance_of(&wrap_near, &account_id)
+            .await
+            .map_err(AgentError::rpc)?,
+    );
+    if balance.is_zero() {
+        return Ok(TransactionResponse::message("You don't have any wNEAR."));
+    }
+    let amount = match amount {
+        HumanAmount::All => balance,
+        HumanAmount::Exact(amount) => {
+            NearAmount::from_yocto(TokenAmount::parse_human(&amount, NEAR_DECIMALS, "wNEAR")?.raw())
+        }
+    };
+    if amount > balance {
+        return Ok(TransactionResponse::message(format!(
+            "You only have {} wNEAR.",
+            balance.as_token_amount()
+        )));
+    }
+
+    Ok(TransactionResponse::new(
+        TransactionBatch::single(Transaction::new(wrap_near).function_call(
+            FunctionCallAction::new(
+                "near_withdraw",
+                serde_json::json!({ "amount": amount }),
+                NearAmount::from_yocto(1),
+            ),
+        )),
+        "",
+    ))
+}
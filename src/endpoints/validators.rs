@@ -0,0 +1,362 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use bigdecimal::{BigDecimal, One, RoundingMode};
+use futures_util::StreamExt;
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Reply;
+
+use crate::{
+    error::AgentError,
+    global_state::get_near_price,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{to_plain_string, NearAmount},
+        response::{BalanceJson, ResponseFormat},
+        validators::{
+            get_epoch_validators, get_pool_fee, get_protocol_config, get_total_supply, network_apy,
+            reward_fraction, uptime,
+        },
+    },
+};
+
+/// Used if the user doesn't specify `limit`
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+/// Fees of uncached pools are loaded this many at a time, so that a cold
+/// cache doesn't send hundreds of requests to the RPC at once
+const MAX_CONCURRENT_FEE_REQUESTS: usize = 8;
+
+pub const OPERATION: Operation = Operation {
+    path: "validators",
+    operation_id: "validators",
+    summary: "List NEAR validators with their fees, APY, and uptime",
+    description: "Returns validators with their status (active, proposed for the next epoch, or kicked out), total stake, fee, estimated APY, and uptime in the current epoch. Use it to recommend staking pools to the user based on real data, e.g. active pools with a low fee and high uptime.",
+    response_description: "The validators matching the filters, sorted as requested.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<ValidatorsInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ValidatorsInput {
+    /// Only return validators with this status: `active`, `proposed`, or `kicked`.
+    pub status: Option<ValidatorStatus>,
+    /// How to sort validators: `stake` (largest first, default), `apy` (highest first), `fee` (lowest first), or `uptime` (highest first).
+    pub sort: Option<ValidatorSort>,
+    /// Only return validators with a fee of at most this many percent, like `10`.
+    #[schemars(with = "Option<f64>")]
+    pub max_fee_percent: Option<BigDecimal>,
+    /// Only return validators with an uptime of at least this many percent, like `99`.
+    #[schemars(with = "Option<f64>")]
+    pub min_uptime_percent: Option<BigDecimal>,
+    /// Only return validators whose account ID contains this text.
+    pub search: Option<String>,
+    /// Maximum number of validators to return. Defaults to 20, at most 100.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidatorStatus {
+    /// Validating in the current epoch
+    Active,
+    /// Not validating now, but proposed or selected for the next epoch
+    Proposed,
+    /// Kicked out in the previous epoch and not proposed again
+    Kicked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidatorSort {
+    #[default]
+    Stake,
+    Apy,
+    Fee,
+    Uptime,
+}
+
+struct Validator {
+    account_id: AccountId,
+    status: ValidatorStatus,
+    /// `None` for kicked out validators
+    stake: Option<NearAmount>,
+    /// From 0 to 1, `None` if the account is not a staking pool
+    fee: Option<BigDecimal>,
+    /// From 0 to 1, `None` if not validating in the current epoch
+    uptime: Option<BigDecimal>,
+    /// From 0 to 1, after the fee
+    apy: Option<BigDecimal>,
+    production: Option<Production>,
+    kickout_reason: Option<String>,
+}
+
+/// Blocks and chunks produced in the current epoch
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Production {
+    pub produced_blocks: u64,
+    pub expected_blocks: u64,
+    pub produced_chunks: u64,
+    pub expected_chunks: u64,
+}
+
+/// JSON response of `/validators`
+#[derive(Debug, Serialize)]
+pub struct ValidatorsResponse {
+    /// Estimated yearly reward before fees for full uptime
+    pub network_apy_percent: String,
+    /// Number of validators matching the filters, before `limit`
+    pub total: usize,
+    pub validators: Vec<ValidatorJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidatorJson {
+    pub account_id: AccountId,
+    pub status: ValidatorStatus,
+    /// `null` for kicked out validators
+    pub stake: Option<BalanceJson>,
+    /// `null` if the fee couldn't be loaded, e.g. if it's not a staking pool
+    pub fee_percent: Option<String>,
+    pub apy_percent: Option<String>,
+    pub uptime_percent: Option<String>,
+    pub production: Option<Production>,
+    pub kickout_reason: Option<String>,
+}
+
+pub async fn get_validators(
+    input: ValidatorsInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let ValidatorsInput {
+        status,
+        sort,
+        max_fee_percent,
+        min_uptime_percent,
+        search,
+        limit,
+    } = input;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+    let (network_apy, mut validators) = load_validators().await?;
+    validators.retain(|validator| {
+        status.is_none_or(|status| validator.status == status)
+            && search.as_ref().is_none_or(|search| {
+                validator
+                    .account_id
+                    .as_str()
+                    .contains(&search.to_lowercase())
+            })
+            && max_fee_percent.as_ref().is_none_or(|max_fee| {
+                validator
+                    .fee
+                    .as_ref()
+                    .is_some_and(|fee| fee * BigDecimal::from(100) <= *max_fee)
+            })
+            && min_uptime_percent.as_ref().is_none_or(|min_uptime| {
+                validator
+                    .uptime
+                    .as_ref()
+                    .is_some_and(|uptime| uptime * BigDecimal::from(100) >= *min_uptime)
+            })
+    });
+    sort_validators(&mut validators, sort.unwrap_or_default());
+    let total = validators.len();
+    validators.truncate(limit);
+
+    if format == ResponseFormat::Json {
+        let near_price = get_near_price().await;
+        return Ok(warp::reply::json(&ValidatorsResponse {
+            network_apy_percent: format_percent(&network_apy),
+            total,
+            validators: validators
+                .into_iter()
+                .map(|validator| ValidatorJson {
+                    stake: validator
+                        .stake
+                        .map(|stake| BalanceJson::near(stake, near_price.as_ref())),
+                    fee_percent: validator.fee.as_ref().map(format_percent),
+                    apy_percent: validator.apy.as_ref().map(format_percent),
+                    uptime_percent: validator.uptime.as_ref().map(format_percent),
+                    account_id: validator.account_id,
+                    status: validator.status,
+                    production: validator.production,
+                    kickout_reason: validator.kickout_reason,
+                })
+                .collect(),
+        })
+        .into_response());
+    }
+
+    let mut response = format!(
+        "Estimated network APY before fees: {}%\nShowing {} of {total} validators:",
+        format_percent(&network_apy),
+        validators.len(),
+    );
+    for validator in validators {
+        response.push_str(&format!("\n- {}", describe_validator(&validator)));
+    }
+    Ok(response.into_response())
+}
+
+/// Network APY and all validators that are active, proposed, or were kicked
+/// out in the previous epoch
+async fn load_validators() -> Result<(BigDecimal, Vec<Validator>), AgentError> {
+    let epoch = get_epoch_validators().await.map_err(AgentError::rpc)?;
+    let config = get_protocol_config().await.map_err(AgentError::rpc)?;
+    let total_supply = get_total_supply().await.map_err(AgentError::rpc)?;
+    let total_stake = epoch
+        .current_validators
+        .iter()
+        .map(|validator| validator.stake)
+        .sum();
+    let network_apy = network_apy(&config, total_supply, total_stake);
+
+    let mut validators: HashMap<AccountId, Validator> = HashMap::new();
+    for current in &epoch.current_validators {
+        let validator = add_validator(
+            &mut validators,
+            &current.account_id,
+            ValidatorStatus::Active,
+            Some(current.stake),
+        );
+        validator.uptime = uptime(current);
+        validator.production = Some(Production {
+            produced_blocks: current.num_produced_blocks,
+            expected_blocks: current.num_expected_blocks,
+            produced_chunks: current.num_produced_chunks,
+            expected_chunks: current.num_expected_chunks,
+        });
+    }
+    for next in &epoch.next_validators {
+        add_validator(
+            &mut validators,
+            &next.account_id,
+            ValidatorStatus::Proposed,
+            Some(next.stake),
+        );
+    }
+    for proposal in &epoch.current_proposals {
+        add_validator(
+            &mut validators,
+            &proposal.account_id,
+            ValidatorStatus::Proposed,
+            Some(proposal.stake),
+        );
+    }
+    for kickout in &epoch.prev_epoch_kickout {
+        let validator = add_validator(
+            &mut validators,
+            &kickout.account_id,
+            ValidatorStatus::Kicked,
+            None,
+        );
+        validator.kickout_reason = Some(kickout.reason_name());
+    }
+
+    let mut validators = validators.into_values().collect::<Vec<_>>();
+    let pool_ids = validators
+        .iter()
+        .map(|validator| validator.account_id.clone())
+        .collect::<Vec<_>>();
+    let mut fees = futures_util::stream::iter(pool_ids.into_iter().enumerate())
+        .map(|(i, pool_id)| async move { (i, get_pool_fee(&pool_id).await) })
+        .buffer_unordered(MAX_CONCURRENT_FEE_REQUESTS)
+        .collect::<Vec<_>>()
+        .await;
+    fees.sort_by_key(|(i, _)| *i);
+    for (validator, (_, fee)) in validators.iter_mut().zip(fees) {
+        validator.fee = fee.ok();
+        if validator.status == ValidatorStatus::Kicked {
+            continue;
+        }
+        // Proposed validators are assumed to have full uptime
+        let reward_fraction = validator
+            .uptime
+            .as_ref()
+            .map_or_else(BigDecimal::one, |uptime| reward_fraction(uptime, &config));
+        validator.apy = validator
+            .fee
+            .as_ref()
+            .map(|fee| &network_apy * reward_fraction * (BigDecimal::one() - fee));
+    }
+    Ok((network_apy, validators))
+}
+
+/// Adds a validator if it's not added yet. Statuses are added in order of
+/// priority, so a validator that is active keeps that status.
+fn add_validator<'a>(
+    validators: &'a mut HashMap<AccountId, Validator>,
+    account_id: &AccountId,
+    status: ValidatorStatus,
+    stake: Option<NearAmount>,
+) -> &'a mut Validator {
+    validators
+        .entry(account_id.clone())
+        .or_insert_with(|| Validator {
+            account_id: account_id.clone(),
+            status,
+            stake,
+            fee: None,
+            uptime: None,
+            apy: None,
+            production: None,
+            kickout_reason: None,
+        })
+}
+
+/// Validators without the value being sorted by go last. Ties are sorted by
+/// stake.
+fn sort_validators(validators: &mut [Validator], sort: ValidatorSort) {
+    validators.sort_by_key(|validator| Reverse(validator.stake));
+    match sort {
+        ValidatorSort::Stake => (),
+        ValidatorSort::Apy => validators.sort_by(|a, b| b.apy.cmp(&a.apy)),
+        ValidatorSort::Fee => validators.sort_by(|a, b| match (&a.fee, &b.fee) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        }),
+        ValidatorSort::Uptime => validators.sort_by(|a, b| b.uptime.cmp(&a.uptime)),
+    }
+}
+
+fn describe_validator(validator: &Validator) -> String {
+    let mut parts = vec![match validator.status {
+        ValidatorStatus::Active => "active".to_string(),
+        ValidatorStatus::Proposed => "proposed for the next epoch".to_string(),
+        ValidatorStatus::Kicked => format!(
+            "kicked out in the previous epoch ({})",
+            validator
+                .kickout_reason
+                .as_deref()
+                .unwrap_or("unknown reason")
+        ),
+    }];
+    if let Some(stake) = validator.stake {
+        parts.push(format!("{} staked", stake.as_token_amount().format("NEAR")));
+    }
+    parts.push(match &validator.fee {
+        Some(fee) => format!("fee {}%", format_percent(fee)),
+        None => "fee unknown, may not be a staking pool".to_string(),
+    });
+    if let Some(apy) = &validator.apy {
+        parts.push(format!("APY ~{}%", format_percent(apy)));
+    }
+    if let (Some(uptime), Some(production)) = (&validator.uptime, validator.production) {
+        parts.push(format!(
+            "uptime {}% (blocks {}/{}, chunks {}/{})",
+            format_percent(uptime),
+            production.produced_blocks,
+            production.expected_blocks,
+            production.produced_chunks,
+            production.expected_chunks,
+        ));
+    }
+    format!("{}: {}", validator.account_id, parts.join(", "))
+}
+
+/// 0.0512 -> `5.12`
+fn format_percent(fraction: &BigDecimal) -> String {
+    to_plain_string(&(fraction * BigDecimal::from(100)).with_scale_round(2, RoundingMode::HalfUp))
+}
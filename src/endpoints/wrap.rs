@@ -0,0 +1,92 @@
+use near_primitives::types::AccountId;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    error::AgentError,
+    manifest::{input_schema, Operation, ResponseBody},
+    utils::{
+        amount::{HumanAmount, NearAmount},
+        formatting::WRAP_NEAR,
+        ft::storage_deposit_needed,
+        response::ResponseFormat,
+        rpc::get_spendable_balance,
+        transaction::{FunctionCallAction, Transaction, TransactionBatch, TransactionResponse},
+    },
+};
+
+pub const OPERATION: Operation = Operation {
+    path: "wrap",
+    operation_id: "wrap",
+    summary: "Generate a transaction that wraps NEAR into wNEAR",
+    description: "Returns a transaction that converts NEAR to wNEAR (wrap.near) 1:1. wNEAR is needed for some DeFi apps, NEAR is needed for gas and staking.",
+    response_description: "The transaction that wraps NEAR.",
+    response: ResponseBody::Text,
+    input_schema: input_schema::<WrapInput>,
+};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct WrapInput {
+    /// The account ID of the user who is wrapping NEAR.
+    #[schemars(with = "String")]
+    pub account_id: AccountId,
+    /// The amount of NEAR to wrap, like `1.5`.
+    #[schemars(with = "String")]
+    pub amount: HumanAmount,
+}
+
+pub async fn create_wrap_transaction(
+    input: WrapInput,
+    format: ResponseFormat,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(wrap(input).await?.into_reply(format))
+}
+
+async fn wrap(input: WrapInput) -> Result<TransactionResponse, AgentError> {
+    let WrapInput { account_id, amount } = input;
+    let HumanAmount::Exact(amount) = amount else {
+        return Ok(TransactionResponse::message(
+            "Wrapping all NEAR is not supported, because some NEAR is needed for gas. Ask the user to specify an amount.",
+        ));
+    };
+    let amount = NearAmount::parse_human(&amount)?;
+
+    let wrap_near: AccountId = WRAP_NEAR.parse().unwrap();
+    let spendable_balance = get_spendable_balance(&account_id)
+        .await
+        .map_err(AgentError::rpc)?;
+    let available = spendable_balance.spendable;
+    let storage_deposit = storage_deposit_needed(&wrap_near, &account_id)
+        .await
+        .map_err(AgentError::rpc)?;
+    if amount + storage_deposit.unwrap_or(NearAmount::ZERO) > available {
+        return Ok(TransactionResponse::message(format!(
+            "You only have {available} available, which is not enough to wrap {amount}{}. The account has {}, of which {}.",
+            match storage_deposit {
+                Some(deposit) => format!(" and pay {deposit} for wNEAR storage"),
+                None => "".to_string(),
+            },
+            spendable_balance.balance,
+            spendable_balance.describe_reserved(),
+        )));
+    }
+
+    let mut transaction = Transaction::new(wrap_near);
+    let mut message = String::new();
+    if let Some(deposit) = storage_deposit {
+        transaction =
+            transaction.function_call(FunctionCallAction::storage_deposit(&account_id, deposit));
+        message = format!(
+            "After that, let the user know that the transaction also pays {deposit} to register on wrap.near, since it's the first time they have wNEAR.",
+        );
+    }
+    let transaction = transaction.function_call(FunctionCallAction::new(
+        "near_deposit",
+        serde_json::json!({}),
+        amount,
+    ));
+    Ok(TransactionResponse::new(
+        TransactionBatch::single(transaction),
+        message,
+    ))
+}
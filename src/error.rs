@@ -0,0 +1,168 @@
+use std::convert::Infallible;
+
+use serde::Serialize;
+use warp::{http::StatusCode, reject::Rejection, reply::Reply};
+
+use crate::utils::rpc::RpcError;
+
+#[derive(Debug)]
+pub enum AgentError {
+    /// A parameter that should be an account ID isn't a valid one
+    InvalidAccount(String),
+    InvalidInput(String),
+    AccountNotFound(String),
+    /// A contract call failed, e.g. because the contract doesn't have the method
+    Contract(String),
+    /// All RPCs failed
+    Rpc(RpcError),
+    FastNear(anyhow::Error),
+    /// An upstream service responded with 429
+    RateLimited,
+    Internal(anyhow::Error),
+}
+
+/// Also makes `AgentError` convertible to `warp::Rejection` with `?`
+impl warp::reject::Reject for AgentError {}
+
+impl AgentError {
+    pub fn rpc(err: RpcError) -> Self {
+        match err {
+            RpcError::UnknownAccount(account_id) => AgentError::AccountNotFound(account_id),
+            RpcError::RateLimited => AgentError::RateLimited,
+            RpcError::NoContractCode(_) | RpcError::ContractExecution(_) => {
+                AgentError::Contract(err.to_string())
+            }
+            err => AgentError::Rpc(err),
+        }
+    }
+
+    pub fn fastnear(err: anyhow::Error) -> Self {
+        if is_rate_limited(&err) {
+            AgentError::RateLimited
+        } else {
+            AgentError::FastNear(err)
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            AgentError::InvalidAccount(_) | AgentError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            AgentError::AccountNotFound(_) => StatusCode::NOT_FOUND,
+            AgentError::Contract(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AgentError::Rpc(_) | AgentError::FastNear(_) => StatusCode::BAD_GATEWAY,
+            AgentError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            AgentError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AgentError::InvalidAccount(_) => "invalid_account",
+            AgentError::InvalidInput(_) => "invalid_input",
+            AgentError::AccountNotFound(_) => "account_not_found",
+            AgentError::Contract(_) => "contract_error",
+            AgentError::Rpc(_) => "rpc_error",
+            AgentError::FastNear(_) => "fastnear_error",
+            AgentError::RateLimited => "rate_limited",
+            AgentError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Written to be shown to the LLM, so it can explain the problem to the user
+    fn message(&self) -> String {
+        match self {
+            AgentError::InvalidAccount(account_id) => format!(
+                "`{account_id}` is not a valid NEAR account ID. Ask the user for the full account ID, like `example.near` or a 64-character implicit account."
+            ),
+            AgentError::InvalidInput(message) => format!("Invalid input: {message}"),
+            AgentError::AccountNotFound(account_id) => format!(
+                "Account `{account_id}` does not exist. Ask the user to double-check the account ID."
+            ),
+            AgentError::Contract(message) => format!("Contract call failed: {message}"),
+            AgentError::Rpc(_) => "Failed to get data from NEAR RPC. Tell the user to try again later, and if the problem persists, to report it in https://t.me/intearchat".to_string(),
+            AgentError::FastNear(_) => "Failed to get data from FastNear API. Tell the user to try again later, and if the problem persists, to report it in https://t.me/intearchat".to_string(),
+            AgentError::RateLimited => "The agent is being rate limited by an upstream service. Tell the user to try again in a minute.".to_string(),
+            AgentError::Internal(_) => "Internal error in the agent. Tell the user to report it in https://t.me/intearchat".to_string(),
+        }
+    }
+}
+
+fn is_rate_limited(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(|err| err.status())
+            == Some(reqwest::StatusCode::TOO_MANY_REQUESTS)
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: &'static str,
+    message: String,
+}
+
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Infallible> {
+    let (status, body) = if let Some(err) = rejection.find::<AgentError>() {
+        match err {
+            AgentError::Rpc(e) => log::error!("{}: {e:?}", err.code()),
+            AgentError::FastNear(e) | AgentError::Internal(e) => {
+                log::error!("{}: {e:?}", err.code());
+            }
+            _ => log::info!("{}: {}", err.code(), err.message()),
+        }
+        (
+            err.status(),
+            ErrorResponse {
+                error: err.code(),
+                message: err.message(),
+            },
+        )
+    } else if rejection.is_not_found() {
+        (
+            StatusCode::NOT_FOUND,
+            ErrorResponse {
+                error: "not_found",
+                message: "Not Found".to_string(),
+            },
+        )
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            ErrorResponse {
+                error: "method_not_allowed",
+                message: "Method Not Allowed".to_string(),
+            },
+        )
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorResponse {
+                error: "payload_too_large",
+                message: "Payload Too Large".to_string(),
+            },
+        )
+    } else if rejection
+        .find::<warp::reject::UnsupportedMediaType>()
+        .is_some()
+    {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorResponse {
+                error: "unsupported_media_type",
+                message: "Unsupported Media Type".to_string(),
+            },
+        )
+    } else {
+        // Debug output of warp's rejections is not meant for users
+        log::warn!("Unhandled rejection: {rejection:?}");
+        (
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "bad_request",
+                message: "Bad Request".to_string(),
+            },
+        )
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&body), status))
+}
@@ -1,33 +1,65 @@
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use near_primitives::serialize::dec_format;
 use near_primitives::types::AccountId;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{OnceCell, RwLock};
+use tokio::sync::RwLock;
+
+use crate::{
+    config::CONFIG,
+    utils::{amount::TokenAmount, formatting::WRAP_NEAR, rpc::get_reqwest_client},
+};
 
 lazy_static! {
-    pub static ref TOKENS: OnceCell<Arc<RwLock<Tokens>>> = OnceCell::new();
+    /// Empty until the first successful refresh
+    pub static ref TOKENS: RwLock<Tokens> = RwLock::new(Tokens::default());
+    /// Prices recorded on each token list refresh, used when the price
+    /// service has no history for a token. Only the largest tokens are
+    /// recorded, see `MAX_PRICE_HISTORY_TOKENS`.
+    static ref PRICE_HISTORY: RwLock<HashMap<AccountId, LocalPriceHistory>> =
+        RwLock::new(HashMap::new());
 }
 
+/// Recent prices are kept more often, so that short-term changes are
+/// accurate without keeping every refresh for a month
+const RECENT_PRICE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const RECENT_PRICE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const HOURLY_PRICE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// A bit more than 30 days, so that there's a price from 30 days ago
+const HOURLY_PRICE_MAX_AGE: Duration = Duration::from_secs(31 * 24 * 60 * 60);
+/// Prices are recorded for at most this many tokens with the highest market
+/// cap, about 16 kB each. Small tokens usually have history in the price
+/// service anyway.
+const MAX_PRICE_HISTORY_TOKENS: usize = 500;
+
 pub async fn is_spam_token(token: &AccountId) -> bool {
+    TOKENS.read().await.spam_tokens.contains(token)
+}
+
+/// A reputable token with this symbol, ignoring case and `$`
+pub async fn find_reputable_token_by_symbol(symbol: &str) -> Option<Token> {
+    let symbol = symbol.trim_matches('$').to_lowercase();
     TOKENS
-        .get()
-        .unwrap()
         .read()
         .await
-        .spam_tokens
-        .contains(token)
+        .tokens
+        .values()
+        .find(|token| {
+            token.reputation == TokenScore::Reputable
+                && token.metadata.symbol.trim_matches('$').to_lowercase() == symbol
+        })
+        .cloned()
 }
 
 pub async fn get_ft_metadata(token: &AccountId) -> Option<TokenMetadataWithoutIcon> {
     TOKENS
-        .get()
-        .unwrap()
         .read()
         .await
         .tokens
@@ -35,21 +67,279 @@ pub async fn get_ft_metadata(token: &AccountId) -> Option<TokenMetadataWithoutIc
         .map(|t| t.metadata.clone())
 }
 
-pub async fn get_ft_price(token: &AccountId) -> Option<f64> {
+pub async fn get_token(token: &AccountId) -> Option<Token> {
+    TOKENS.read().await.tokens.get(token).cloned()
+}
+
+pub async fn get_ft_price(token: &AccountId) -> Option<BigDecimal> {
     TOKENS
-        .get()
-        .unwrap()
         .read()
         .await
         .tokens
         .get(token)
-        .map(|t| t.price_usd_hardcoded.to_string().parse().unwrap())
+        .map(|t| t.price_usd_hardcoded.clone())
+}
+
+pub async fn get_near_price() -> Option<BigDecimal> {
+    get_ft_price(&WRAP_NEAR.parse().unwrap()).await
+}
+
+/// Locally recorded prices of a token since `since`, oldest first
+pub async fn get_local_price_history(token: &AccountId, since: SystemTime) -> Vec<PricePoint> {
+    PRICE_HISTORY
+        .read()
+        .await
+        .get(token)
+        .map(|history| history.since(since))
+        .unwrap_or_default()
+}
+
+/// Records prices of the `MAX_PRICE_HISTORY_TOKENS` tokens with the highest
+/// market cap, and forgets tokens that are no longer among them
+async fn record_prices(tokens: &Tokens) {
+    let Some(timestamp) = tokens.updated_at else {
+        return;
+    };
+    let wrap_near: AccountId = WRAP_NEAR.parse().unwrap();
+    let recorded = tokens
+        .tokens
+        .values()
+        .filter(|token| {
+            !token.price_usd.is_zero() && !tokens.spam_tokens.contains(&token.account_id)
+        })
+        .sorted_by_cached_key(|token| {
+            // NEAR first, it's always recorded
+            let market_cap =
+                TokenAmount::from_raw(token.circulating_supply, token.metadata.decimals)
+                    .usd_value(&token.price_usd);
+            (token.account_id != wrap_near, std::cmp::Reverse(market_cap))
+        })
+        .take(MAX_PRICE_HISTORY_TOKENS)
+        .collect::<Vec<_>>();
+
+    let mut history = PRICE_HISTORY.write().await;
+    history.retain(|token_id, _| recorded.iter().any(|token| &token.account_id == token_id));
+    for token in recorded {
+        let Some(point) = StoredPricePoint::new(timestamp, &token.price_usd) else {
+            continue;
+        };
+        history
+            .entry(token.account_id.clone())
+            .or_default()
+            .record(point);
+    }
+}
+
+/// Time since the token list was last fetched, `None` if it was never loaded
+pub async fn get_tokens_age() -> Option<Duration> {
+    TOKENS
+        .read()
+        .await
+        .updated_at
+        .map(|updated_at| updated_at.elapsed().unwrap_or_default())
+}
+
+pub async fn fetch_tokens() -> Result<Tokens, anyhow::Error> {
+    Ok(Tokens {
+        tokens: get_reqwest_client()
+            .get(format!("{}/tokens", CONFIG.prices_api_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?,
+        spam_tokens: get_reqwest_client()
+            .get(format!("{}/token-spam-list", CONFIG.prices_api_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?,
+        updated_at: Some(SystemTime::now()),
+    })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Loads the token list saved by a previous run, if it's not too old
+pub async fn load_tokens_snapshot() {
+    let Some(path) = &CONFIG.snapshot.path else {
+        return;
+    };
+    if !path.exists() {
+        log::info!("No token snapshot at {}", path.display());
+        return;
+    }
+    let result: Result<Tokens, anyhow::Error> = async {
+        let tokens: Tokens = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+        let age = tokens
+            .updated_at
+            .map(|updated_at| updated_at.elapsed().unwrap_or_default())
+            .ok_or_else(|| anyhow::anyhow!("Snapshot has no timestamp"))?;
+        if age > CONFIG.snapshot.max_age() {
+            anyhow::bail!("Snapshot is too old: {}s", age.as_secs());
+        }
+        Ok(tokens)
+    }
+    .await;
+    match result {
+        Ok(tokens) => {
+            log::info!(
+                "Loaded {} tokens from {}",
+                tokens.tokens.len(),
+                path.display()
+            );
+            record_prices(&tokens).await;
+            *TOKENS.write().await = tokens;
+        }
+        Err(err) => log::warn!("Not using token snapshot {}: {err:?}", path.display()),
+    }
+}
+
+/// Writes to a temporary file first, so that a crash in the middle of writing
+/// doesn't leave a corrupted snapshot
+async fn save_tokens_snapshot(tokens: &Tokens) -> Result<(), anyhow::Error> {
+    let Some(path) = &CONFIG.snapshot.path else {
+        return Ok(());
+    };
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_vec(tokens)?).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Refreshes the token list forever. Until the first successful refresh,
+/// retries more often, so that prices become available soon after the price
+/// service comes back up.
+pub async fn refresh_tokens_loop() {
+    loop {
+        let is_loaded = TOKENS.read().await.updated_at.is_some();
+        match fetch_tokens().await {
+            Ok(new_tokens) => {
+                log::info!("Cache refreshed");
+                if let Err(err) = save_tokens_snapshot(&new_tokens).await {
+                    log::error!("Failed to save token snapshot: {err:?}");
+                }
+                record_prices(&new_tokens).await;
+                *TOKENS.write().await = new_tokens;
+                tokio::time::sleep(CONFIG.token_refresh_interval()).await;
+            }
+            Err(err) if is_loaded => {
+                log::error!("Failed to refresh cache: {err:?}");
+                tokio::time::sleep(CONFIG.token_refresh_interval()).await;
+            }
+            Err(err) => {
+                log::error!("Failed to load tokens, serving responses without prices: {err:?}");
+                tokio::time::sleep(CONFIG.token_retry_interval()).await;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PricePoint {
+    pub timestamp: SystemTime,
+    pub price_usd: BigDecimal,
+}
+
+/// Compact `PricePoint` for the local history, the price is only used for
+/// charts and changes in percent, so `f64` is precise enough
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StoredPricePoint {
+    /// Unix timestamp in seconds
+    timestamp: u32,
+    price_usd: f64,
+}
+
+impl StoredPricePoint {
+    fn new(timestamp: SystemTime, price_usd: &BigDecimal) -> Option<Self> {
+        Some(Self {
+            timestamp: timestamp
+                .duration_since(UNIX_EPOCH)
+                .ok()?
+                .as_secs()
+                .try_into()
+                .ok()?,
+            price_usd: price_usd.to_f64().filter(|price| price.is_finite())?,
+        })
+    }
+
+    fn to_price_point(self) -> PricePoint {
+        PricePoint {
+            timestamp: UNIX_EPOCH + Duration::from_secs(self.timestamp.into()),
+            // The shortest representation, not the exact binary value
+            price_usd: BigDecimal::from_str(&self.price_usd.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LocalPriceHistory {
+    /// Every `RECENT_PRICE_INTERVAL` for `RECENT_PRICE_MAX_AGE`
+    recent: VecDeque<StoredPricePoint>,
+    /// Every `HOURLY_PRICE_INTERVAL` for `HOURLY_PRICE_MAX_AGE`
+    hourly: VecDeque<StoredPricePoint>,
+}
+
+impl LocalPriceHistory {
+    fn record(&mut self, point: StoredPricePoint) {
+        push_price_point(
+            &mut self.recent,
+            point,
+            RECENT_PRICE_INTERVAL,
+            RECENT_PRICE_MAX_AGE,
+        );
+        push_price_point(
+            &mut self.hourly,
+            point,
+            HOURLY_PRICE_INTERVAL,
+            HOURLY_PRICE_MAX_AGE,
+        );
+    }
+
+    /// Hourly points until recent ones are available, then recent ones
+    fn since(&self, since: SystemTime) -> Vec<PricePoint> {
+        let since = since
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let recent_start = self.recent.front().map(|point| point.timestamp);
+        self.hourly
+            .iter()
+            .filter(|point| recent_start.is_none_or(|start| point.timestamp < start))
+            .chain(self.recent.iter())
+            .filter(|point| u64::from(point.timestamp) >= since)
+            .map(|point| point.to_price_point())
+            .collect()
+    }
+}
+
+/// Skips `point` if the last one is less than `interval` old, and drops
+/// points older than `max_age`
+fn push_price_point(
+    points: &mut VecDeque<StoredPricePoint>,
+    point: StoredPricePoint,
+    interval: Duration,
+    max_age: Duration,
+) {
+    let age = |older: &StoredPricePoint| {
+        Duration::from_secs(point.timestamp.saturating_sub(older.timestamp).into())
+    };
+    if points.back().is_some_and(|last| age(last) < interval) {
+        return;
+    }
+    while points.front().is_some_and(|first| age(first) > max_age) {
+        points.pop_front();
+    }
+    points.push_back(point);
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Tokens {
     pub tokens: HashMap<AccountId, Token>,
     pub spam_tokens: HashSet<AccountId>,
+    /// When the token list was fetched from the price service
+    #[serde(default)]
+    pub updated_at: Option<SystemTime>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,3 +399,74 @@ mod serde_bigdecimal {
         BigDecimal::from_str(&s).map_err(D::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp: u32, price_usd: f64) -> StoredPricePoint {
+        StoredPricePoint {
+            timestamp,
+            price_usd,
+        }
+    }
+
+    fn timestamps(points: &VecDeque<StoredPricePoint>) -> Vec<u32> {
+        points.iter().map(|point| point.timestamp).collect()
+    }
+
+    #[test]
+    fn push_price_point_interval_and_max_age() {
+        let interval = Duration::from_secs(300);
+        let max_age = Duration::from_secs(1000);
+        let mut points = VecDeque::new();
+        for timestamp in [0, 100, 300, 600, 900] {
+            push_price_point(&mut points, point(timestamp, 1.0), interval, max_age);
+        }
+        // 100 is too soon after 0
+        assert_eq!(timestamps(&points), [0, 300, 600, 900]);
+
+        push_price_point(&mut points, point(1400, 1.0), interval, max_age);
+        assert_eq!(timestamps(&points), [600, 900, 1400]);
+    }
+
+    #[test]
+    fn since_prefers_recent_points() {
+        let history = LocalPriceHistory {
+            recent: [point(7000, 3.0), point(7300, 4.0)].into(),
+            hourly: [point(0, 1.0), point(3600, 2.0), point(7200, 3.5)].into(),
+        };
+        let since = |secs| {
+            history
+                .since(UNIX_EPOCH + Duration::from_secs(secs))
+                .into_iter()
+                .map(|point| {
+                    (
+                        point
+                            .timestamp
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs(),
+                        point.price_usd.to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let all = [(0, "1"), (3600, "2"), (7000, "3"), (7300, "4")]
+            .map(|(timestamp, price)| (timestamp, price.to_string()));
+        assert_eq!(since(0), all);
+        assert_eq!(since(3600), all[1..]);
+        assert_eq!(since(7100), all[3..]);
+        assert!(since(8000).is_empty());
+    }
+
+    #[test]
+    fn stored_price_point() {
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let point = StoredPricePoint::new(timestamp, &BigDecimal::from_str("1.23").unwrap())
+            .unwrap()
+            .to_price_point();
+        assert_eq!(point.timestamp, timestamp);
+        assert_eq!(point.price_usd.to_string(), "1.23");
+    }
+}
@@ -1,19 +1,30 @@
+mod agents;
+mod config;
 mod endpoints;
+mod error;
 mod global_state;
+mod manifest;
 mod utils;
 
-use std::sync::Arc;
-
+use config::CONFIG;
 use endpoints::{
-    stake::{create_stake_transaction, StakeInput},
-    staking::{get_staking, GetStakingInput},
-    token_prices::{get_token_prices, TokenPricesInput},
-    total_balance::{get_total_balance, WrappedAccountId},
-    unstake::{create_unstake_transaction, UnstakeInput},
+    health::{get_healthz, get_readyz, get_version},
+    metrics::get_metrics,
+    resolve_pool::{self, get_resolve_pool, ResolvePoolInput},
+    stake::{self, create_stake_transaction, StakeInput},
+    staking::{self, get_staking, GetStakingInput},
+    swap::{self, create_swap_transaction, SwapInput},
+    swap_quote::{self, get_swap_quote, SwapQuoteInput},
+    token_history::{self, get_token_history, TokenHistoryInput},
+    token_info::{self, get_token_info, TokenInfoInput},
+    token_prices::{self, get_token_prices, TokenPricesInput},
+    total_balance::{self, get_total_balance, WrappedAccountId},
+    transfer::{self, create_transfer_transaction, TransferInput},
+    unstake::{self, create_unstake_transaction, UnstakeInput},
+    unwrap::{self, create_unwrap_transaction, UnwrapInput},
+    validators::{self, get_validators, ValidatorsInput},
+    wrap::{self, create_wrap_transaction, WrapInput},
 };
-use global_state::Tokens;
-use tokio::sync::RwLock;
-use utils::rpc::get_reqwest_client;
 use warp::{filters::header::header, reply::Response, Filter};
 
 #[tokio::main]
@@ -22,144 +33,163 @@ async fn main() {
         .with_level(log::LevelFilter::Info)
         .init()
         .unwrap();
+    lazy_static::initialize(&CONFIG);
 
-    global_state::TOKENS
-        .get_or_init(|| async {
-            Arc::new(RwLock::new(Tokens {
-                tokens: get_reqwest_client()
-                    .get("https://prices.intear.tech/tokens")
-                    .send()
-                    .await
-                    .unwrap()
-                    .json()
-                    .await
-                    .unwrap(),
-                spam_tokens: get_reqwest_client()
-                    .get("https://prices.intear.tech/token-spam-list")
-                    .send()
-                    .await
-                    .unwrap()
-                    .json()
-                    .await
-                    .unwrap(),
-            }))
-        })
-        .await;
-    tokio::spawn(async {
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
-        loop {
-            let result: Result<(), Box<dyn std::error::Error>> = async {
-                interval.tick().await;
-                let new_tokens = Tokens {
-                    tokens: get_reqwest_client()
-                        .get("https://prices.intear.tech/tokens")
-                        .send()
-                        .await?
-                        .json()
-                        .await?,
-                    spam_tokens: get_reqwest_client()
-                        .get("https://prices.intear.tech/token-spam-list")
-                        .send()
-                        .await?
-                        .json()
-                        .await?,
-                };
-                log::info!("Cache refreshed");
-                *global_state::TOKENS.get().unwrap().write().await = new_tokens;
-                Ok(())
-            }
-            .await;
-            if let Err(err) = result {
-                log::error!("Failed to refresh cache: {err:?}");
-            }
-        }
-    });
+    global_state::load_tokens_snapshot().await;
+    tokio::spawn(global_state::refresh_tokens_loop());
 
+    lazy_static::initialize(&agents::AGENTS);
     let manifest = warp::path!(".well-known" / "ai-plugin.json")
+        .and(agents::agent())
         .and(header("Host"))
-        .map(|host: String| {
+        .map(|agent: Option<&'static agents::Agent>, host: String| {
             log::info!("Sending ai-plugin.json");
 
-            let mut res = Response::new(
-                match host.split('.').next() {
-                    Some("staking-agent") => {
-                        include_str!("../staking-agent.json")
-                    }
-                    Some("tokens-agent") => {
-                        include_str!("../tokens-agent.json")
-                    }
-                    _ => {
-                        #[cfg(feature = "local-debug-agent")]
-                        {
-                            include_str!(concat!("../", env!("DEBUG_AGENT"), "-agent.json"))
-                        }
-                        #[cfg(not(feature = "local-debug-agent"))]
-                        {
-                            log::warn!("Unknown host: {host}");
-                            let mut response = Response::new("Unknown host".into());
-                            *response.status_mut() = warp::http::StatusCode::BAD_REQUEST;
-                            return response;
-                        }
-                    }
-                }
-                .into(),
-            );
+            let Some(agent) = agent else {
+                log::warn!("Unknown host: {host}");
+                let mut response = Response::new("Unknown host".into());
+                *response.status_mut() = warp::http::StatusCode::BAD_REQUEST;
+                return response;
+            };
+
+            let mut res = Response::new(agent.manifest.clone().into());
             res.headers_mut()
                 .insert("content-type", "application/json".parse().unwrap());
             res
         });
 
-    let total_balance = warp::path("total-balance")
-        .and(warp::query::query::<WrappedAccountId>())
-        .and_then(|input| {
+    let health = warp::path("healthz")
+        .and(warp::path::end())
+        .and_then(get_healthz)
+        .or(warp::path("readyz")
+            .and(warp::path::end())
+            .and_then(get_readyz))
+        .or(warp::path("version")
+            .and(warp::path::end())
+            .and_then(get_version))
+        .or(warp::path("metrics")
+            .and(warp::path::end())
+            .and_then(get_metrics));
+
+    let total_balance = agents::route(total_balance::OPERATION)
+        .and(utils::query::query::<WrappedAccountId>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
             log::info!("Sending total-balance for account_id: {input:?}");
-            get_total_balance(input)
+            get_total_balance(input, format)
         });
-    let token_prices = warp::path("token-prices")
-        .and(warp::query::query::<TokenPricesInput>())
-        .and_then(|input| {
+    let token_prices = agents::route(token_prices::OPERATION)
+        .and(utils::query::query::<TokenPricesInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
             log::info!("Sending token-prices for tokens: {input:?}");
-            get_token_prices(input)
+            get_token_prices(input, format)
+        });
+    let token_history = agents::route(token_history::OPERATION)
+        .and(utils::query::query::<TokenHistoryInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Sending token-history for {input:?}");
+            get_token_history(input, format)
         });
-    let staking = warp::path("staking")
-        .and(warp::query::query::<GetStakingInput>())
-        .and_then(|input| {
+    let token_info = agents::route(token_info::OPERATION)
+        .and(utils::query::query::<TokenInfoInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Sending token-info for {input:?}");
+            get_token_info(input, format)
+        });
+    let staking = agents::route(staking::OPERATION)
+        .and(utils::query::query::<GetStakingInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
             log::info!("Sending staking for tokens: {input:?}");
-            get_staking(input)
+            get_staking(input, format)
+        });
+    let resolve_pool = agents::route(resolve_pool::OPERATION)
+        .and(utils::query::query::<ResolvePoolInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Sending resolve-pool for {input:?}");
+            get_resolve_pool(input, format)
         });
-    let stake = warp::path("stake")
-        .and(warp::query::query::<StakeInput>())
-        .and_then(|input| {
+    let stake = agents::route(stake::OPERATION)
+        .and(utils::query::query::<StakeInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
             log::info!("Creating stake transaction for {input:?}");
-            create_stake_transaction(input)
+            create_stake_transaction(input, format)
         });
-    let unstake = warp::path("unstake")
-        .and(warp::query::query::<UnstakeInput>())
-        .and_then(|input| {
+    let unstake = agents::route(unstake::OPERATION)
+        .and(utils::query::query::<UnstakeInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
             log::info!("Creating unstake transaction for {input:?}");
-            create_unstake_transaction(input)
+            create_unstake_transaction(input, format)
+        });
+    let validators = agents::route(validators::OPERATION)
+        .and(utils::query::query::<ValidatorsInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Sending validators for {input:?}");
+            get_validators(input, format)
+        });
+    let transfer = agents::route(transfer::OPERATION)
+        .and(utils::query::query::<TransferInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Creating transfer transaction for {input:?}");
+            create_transfer_transaction(input, format)
+        });
+    let wrap = agents::route(wrap::OPERATION)
+        .and(utils::query::query::<WrapInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Creating wrap transaction for {input:?}");
+            create_wrap_transaction(input, format)
+        });
+    let unwrap = agents::route(unwrap::OPERATION)
+        .and(utils::query::query::<UnwrapInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Creating unwrap transaction for {input:?}");
+            create_unwrap_transaction(input, format)
+        });
+    let swap_quote = agents::route(swap_quote::OPERATION)
+        .and(utils::query::query::<SwapQuoteInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Sending swap-quote for {input:?}");
+            get_swap_quote(input, format)
+        });
+    let swap = agents::route(swap::OPERATION)
+        .and(utils::query::query::<SwapInput>())
+        .and(utils::response::response_format())
+        .and_then(|input, format| {
+            log::info!("Creating swap transaction for {input:?}");
+            create_swap_transaction(input, format)
         });
     let api = total_balance
         .or(token_prices)
+        .or(token_history)
+        .or(token_info)
         .or(staking)
+        .or(resolve_pool)
         .or(stake)
-        .or(unstake);
+        .or(unstake)
+        .or(validators)
+        .or(transfer)
+        .or(wrap)
+        .or(unwrap)
+        .or(swap_quote)
+        .or(swap);
 
-    let routes = manifest
-        .or(api)
-        .or(warp::any().and(warp::path::full()).map(|path| {
-            log::debug!("{path:?}");
-            let mut res = Response::new("Not Found".into());
-            *res.status_mut() = warp::http::StatusCode::NOT_FOUND;
-            res
-        }));
+    let routes = health.or(manifest).or(api).recover(error::handle_rejection);
 
-    log::info!("Server started");
-    warp::serve(routes)
-        .run(if cfg!(debug_assertions) {
-            ([127, 0, 0, 1], 3030)
-        } else {
-            ([0, 0, 0, 0], 80)
-        })
-        .await;
+    log::info!("Server started on {}", CONFIG.bind_address);
+    warp::serve(routes.with(warp::log::custom(|info| {
+        utils::metrics::record_request(info.path(), info.status(), info.elapsed())
+    })))
+    .run(CONFIG.bind_address)
+    .await;
 }
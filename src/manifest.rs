@@ -0,0 +1,135 @@
+//! `.well-known/ai-plugin.json` of an agent: an OpenAPI document with
+//! Bitte's `x-mb` metadata. Query parameters are generated from the input
+//! structs of the endpoints, and their descriptions from the doc comments on
+//! the fields, so the manifests can't drift from what the server accepts.
+
+use schemars::{gen::SchemaSettings, schema::RootSchema, JsonSchema};
+use serde_json::{json, Value};
+
+use crate::config::AgentConfig;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Operation {
+    /// Path without the leading slash, also used for the route
+    pub path: &'static str,
+    pub operation_id: &'static str,
+    pub summary: &'static str,
+    pub description: &'static str,
+    pub response_description: &'static str,
+    pub response: ResponseBody,
+    pub input_schema: fn() -> RootSchema,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ResponseBody {
+    /// Text for the LLM
+    Text,
+    /// JSON array of strings for the LLM
+    TextList,
+}
+
+impl Operation {
+    fn to_openapi(self, description: &str) -> Value {
+        let response_schema = match self.response {
+            ResponseBody::Text => json!({ "type": "string" }),
+            ResponseBody::TextList => json!({ "type": "array", "items": { "type": "string" } }),
+        };
+        json!({
+            "get": {
+                "summary": self.summary,
+                "description": description,
+                "operationId": self.operation_id,
+                "parameters": query_parameters((self.input_schema)()),
+                "responses": {
+                    "200": {
+                        "description": self.response_description,
+                        "content": {
+                            "application/json": {
+                                "schema": response_schema,
+                            },
+                        },
+                    },
+                },
+            },
+        })
+    }
+}
+
+/// For `Operation::input_schema`
+pub fn input_schema<T: JsonSchema>() -> RootSchema {
+    SchemaSettings::openapi3()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<T>()
+}
+
+/// Turns the properties of an input struct's schema into OpenAPI query
+/// parameters
+fn query_parameters(schema: RootSchema) -> Vec<Value> {
+    let Some(object) = schema.schema.object else {
+        return Vec::new();
+    };
+    object
+        .properties
+        .into_iter()
+        .map(|(name, property)| {
+            let mut property = property.into_object();
+            let description = property
+                .metadata
+                .as_mut()
+                .and_then(|metadata| metadata.description.take());
+            // Optional parameters are already marked as not required
+            property.extensions.remove("nullable");
+            json!({
+                "name": name,
+                "in": "query",
+                "required": object.required.contains(&name),
+                "description": description,
+                "schema": property,
+            })
+        })
+        .collect()
+}
+
+/// Renders the manifest of an agent that exposes `operations`
+pub fn render_manifest(agent: &AgentConfig, operations: &[Operation]) -> Value {
+    let mut assistant = json!({
+        "name": agent.assistant_name,
+        "description": agent.assistant_description,
+        "instructions": agent.instructions,
+        "tools": agent
+            .tools
+            .iter()
+            .map(|tool| json!({ "type": tool }))
+            .collect::<Vec<_>>(),
+    });
+    if let Some(image) = &agent.image {
+        assistant["image"] = image.as_str().into();
+    }
+    json!({
+        "openapi": "3.0.0",
+        "info": {
+            "title": agent.title,
+            "description": agent.description,
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": agent.server_url }],
+        "x-mb": {
+            "account-id": agent.account_id,
+            "assistant": assistant,
+        },
+        "paths": operations
+            .iter()
+            .map(|operation| {
+                let description = agent
+                    .route_descriptions
+                    .get(operation.path)
+                    .map_or(operation.description, String::as_str);
+                (
+                    format!("/{}", operation.path),
+                    operation.to_openapi(description),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
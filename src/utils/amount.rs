@@ -0,0 +1,425 @@
+use std::{fmt::Display, str::FromStr};
+
+use bigdecimal::{BigDecimal, FromPrimitive, RoundingMode, Signed};
+use near_primitives::serialize::dec_format;
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AgentError;
+
+use super::formatting::NEAR_DECIMALS;
+
+/// Raw amount of a fungible token, together with the token's decimals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenAmount {
+    raw: u128,
+    decimals: u32,
+}
+
+impl TokenAmount {
+    pub const fn from_raw(raw: u128, decimals: u32) -> Self {
+        Self { raw, decimals }
+    }
+
+    /// Exact conversion from a human-readable amount. Returns `None` if the
+    /// amount is negative, has more decimal places than the token, or doesn't
+    /// fit in u128.
+    pub fn from_human(amount: &BigDecimal, decimals: u32) -> Option<Self> {
+        if amount.is_negative() {
+            return None;
+        }
+        let raw = amount * BigDecimal::from_u128(10u128.checked_pow(decimals)?)?;
+        if !raw.is_integer() {
+            return None;
+        }
+        Some(Self::from_raw(raw.to_u128()?, decimals))
+    }
+
+    /// Like `from_human`, with an error that explains the problem to the LLM
+    pub fn parse_human(
+        amount: &BigDecimal,
+        decimals: u32,
+        symbol: &str,
+    ) -> Result<Self, AgentError> {
+        Self::from_human(amount, decimals).ok_or_else(|| {
+            AgentError::InvalidInput(format!(
+                "{} is not a valid {symbol} amount, {symbol} has at most {decimals} decimal places",
+                to_plain_string(amount)
+            ))
+        })
+    }
+
+    pub const fn raw(self) -> u128 {
+        self.raw
+    }
+
+    pub const fn decimals(self) -> u32 {
+        self.decimals
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.raw == 0
+    }
+
+    pub fn to_human(self) -> BigDecimal {
+        BigDecimal::new(self.raw.into(), self.decimals as i64)
+    }
+
+    pub fn usd_value(self, price_usd: &BigDecimal) -> BigDecimal {
+        self.to_human() * price_usd
+    }
+
+    /// Rounds the amount to a few significant digits, e.g. `1.235 USDC`
+    pub fn format(self, symbol: &str) -> String {
+        if self.decimals == 0 || self.raw == 0 {
+            return format!("{} {symbol}", self.raw);
+        }
+        // floor(log10(amount))
+        let magnitude = self.raw.to_string().len() as i64 - 1 - self.decimals as i64;
+        let scale = match magnitude {
+            6.. => 0,
+            1.. => 2,
+            0 => 3,
+            -12..=-1 => 2 - magnitude,
+            _ => return format!("0 {symbol}"),
+        };
+        let rounded = self
+            .to_human()
+            .with_scale_round(scale, bigdecimal::RoundingMode::HalfUp);
+        format!("{} {symbol}", to_plain_string(&rounded))
+    }
+}
+
+impl Display for TokenAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", to_plain_string(&self.to_human()))
+    }
+}
+
+/// Amount of NEAR, stored in yoctoNEAR. Serialized as a yoctoNEAR string,
+/// the format used by RPC and contract arguments.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct NearAmount(#[serde(with = "dec_format")] u128);
+
+impl NearAmount {
+    pub const ZERO: Self = Self(0);
+
+    pub const fn from_yocto(yocto: u128) -> Self {
+        Self(yocto)
+    }
+
+    pub fn from_near(near: &BigDecimal) -> Option<Self> {
+        TokenAmount::from_human(near, NEAR_DECIMALS).map(|amount| Self(amount.raw()))
+    }
+
+    /// Like `from_near`, with an error that explains the problem to the LLM
+    pub fn parse_human(near: &BigDecimal) -> Result<Self, AgentError> {
+        TokenAmount::parse_human(near, NEAR_DECIMALS, "NEAR").map(|amount| Self(amount.raw()))
+    }
+
+    pub const fn as_yocto(self) -> u128 {
+        self.0
+    }
+
+    pub const fn as_token_amount(self) -> TokenAmount {
+        TokenAmount::from_raw(self.0, NEAR_DECIMALS)
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    /// `percent` of the amount, rounded down. `percent` is from 0 to 100.
+    pub fn percent(self, percent: &BigDecimal) -> Self {
+        let yocto = (BigDecimal::from(self.0) * percent / BigDecimal::from(100))
+            .with_scale_round(0, RoundingMode::Down);
+        Self(yocto.to_u128().unwrap_or_default().min(self.0))
+    }
+}
+
+/// Saturates at `u128::MAX`, which is far more than the total supply of NEAR
+impl std::ops::Add for NearAmount {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+}
+
+impl std::iter::Sum for NearAmount {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, |a, b| a + b)
+    }
+}
+
+/// Exact amount, e.g. `1.5 NEAR`
+impl Display for NearAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} NEAR", self.as_token_amount())
+    }
+}
+
+/// Longer amounts are rejected. u128 has 39 digits, so this fits any raw
+/// amount with plenty of decimal places.
+const MAX_AMOUNT_LENGTH: usize = 80;
+
+/// Parses a number entered by the user. Only plain decimal notation like
+/// `-1.5` is accepted: `1e999999999` is a valid `BigDecimal`, but writing it
+/// out with `to_plain_string` would take gigabytes.
+fn parse_plain_decimal(s: &str) -> Option<BigDecimal> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    if s.len() > MAX_AMOUNT_LENGTH
+        || !digits.chars().any(|c| c.is_ascii_digit())
+        || !digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        || digits.matches('.').count() > 1
+    {
+        return None;
+    }
+    BigDecimal::from_str(s).ok()
+}
+
+/// Amount as entered by the user: a decimal number like `1.5`, or `all`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum HumanAmount {
+    All,
+    Exact(BigDecimal),
+}
+
+impl FromStr for HumanAmount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("all") || s.eq_ignore_ascii_case("max") {
+            return Ok(HumanAmount::All);
+        }
+        match parse_plain_decimal(s) {
+            Some(amount) if !amount.is_negative() => Ok(HumanAmount::Exact(amount)),
+            Some(_) => Err(format!("Amount can't be negative: {s}")),
+            None => Err(format!("Invalid amount: {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for HumanAmount {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for HumanAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HumanAmount::All => write!(f, "all"),
+            HumanAmount::Exact(amount) => write!(f, "{}", to_plain_string(amount)),
+        }
+    }
+}
+
+/// Amount of a balance as entered by the user: like `HumanAmount`, but can
+/// also be a percentage of the balance like `50%`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum BalanceAmount {
+    All,
+    /// From 0 to 100
+    Percent(BigDecimal),
+    Exact(BigDecimal),
+}
+
+impl FromStr for BalanceAmount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let Some(percent) = s.strip_suffix('%') else {
+            return Ok(match s.parse()? {
+                HumanAmount::All => BalanceAmount::All,
+                HumanAmount::Exact(amount) => BalanceAmount::Exact(amount),
+            });
+        };
+        match parse_plain_decimal(percent.trim()) {
+            Some(percent) if percent > BigDecimal::from(0) && percent <= BigDecimal::from(100) => {
+                Ok(BalanceAmount::Percent(percent))
+            }
+            Some(_) => Err(format!(
+                "Percentage must be more than 0 and at most 100: {s}"
+            )),
+            None => Err(format!("Invalid percentage: {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for BalanceAmount {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for BalanceAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BalanceAmount::All => write!(f, "all"),
+            BalanceAmount::Percent(percent) => write!(f, "{}%", to_plain_string(percent)),
+            BalanceAmount::Exact(amount) => write!(f, "{}", to_plain_string(amount)),
+        }
+    }
+}
+
+/// BigDecimal's `Display` switches to exponential notation for very small and
+/// very large numbers, which is not something we want to show to users
+pub fn to_plain_string(value: &BigDecimal) -> String {
+    let (int, scale) = value.normalized().into_bigint_and_exponent();
+    let sign = if int.is_negative() { "-" } else { "" };
+    let digits = int.magnitude().to_string();
+    if scale <= 0 {
+        return format!("{sign}{digits}{}", "0".repeat(-scale as usize));
+    }
+    let scale = scale as usize;
+    if digits.len() > scale {
+        let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+        format!("{sign}{int_part}.{frac_part}")
+    } else {
+        format!("{sign}0.{}{digits}", "0".repeat(scale - digits.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn from_human() {
+        assert_eq!(
+            TokenAmount::from_human(&decimal("1.5"), 6),
+            Some(TokenAmount::from_raw(1_500_000, 6))
+        );
+        assert_eq!(
+            TokenAmount::from_human(&decimal("0.000001"), 6),
+            Some(TokenAmount::from_raw(1, 6))
+        );
+        // Too many decimal places
+        assert_eq!(TokenAmount::from_human(&decimal("0.0000001"), 6), None);
+        assert_eq!(TokenAmount::from_human(&decimal("-1"), 6), None);
+        // u128::MAX is about 3.4e38
+        assert_eq!(TokenAmount::from_human(&decimal("1e15"), 24), None);
+        assert_eq!(TokenAmount::from_human(&decimal("1"), 39), None);
+    }
+
+    #[test]
+    fn parse_human() {
+        assert_eq!(
+            NearAmount::parse_human(&decimal("1.5")).unwrap(),
+            NearAmount::from_yocto(1_500_000_000_000_000_000_000_000)
+        );
+        let err = TokenAmount::parse_human(&decimal("0.0000001"), 6, "USDC").unwrap_err();
+        assert!(matches!(
+            err,
+            AgentError::InvalidInput(message)
+                if message == "0.0000001 is not a valid USDC amount, USDC has at most 6 decimal places"
+        ));
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(TokenAmount::from_raw(0, 6).format("USDC"), "0 USDC");
+        assert_eq!(TokenAmount::from_raw(42, 0).format("X"), "42 X");
+        assert_eq!(
+            TokenAmount::from_raw(1_234_567, 6).format("USDC"),
+            "1.235 USDC"
+        );
+        assert_eq!(
+            TokenAmount::from_raw(12_345_678, 6).format("USDC"),
+            "12.35 USDC"
+        );
+        assert_eq!(
+            TokenAmount::from_raw(1_234_567_890_000, 6).format("USDC"),
+            "1234568 USDC"
+        );
+        assert_eq!(
+            TokenAmount::from_raw(1_234, 6).format("USDC"),
+            "0.00123 USDC"
+        );
+        assert_eq!(TokenAmount::from_raw(1, 24).format("NEAR"), "0 NEAR");
+    }
+
+    #[test]
+    fn plain_string() {
+        assert_eq!(to_plain_string(&decimal("1e-7")), "0.0000001");
+        assert_eq!(
+            to_plain_string(&decimal("1.5e30")),
+            "1500000000000000000000000000000"
+        );
+        assert_eq!(to_plain_string(&decimal("-0.25")), "-0.25");
+        assert_eq!(to_plain_string(&decimal("100.00")), "100");
+        assert_eq!(to_plain_string(&decimal("0")), "0");
+    }
+
+    #[test]
+    fn near_amount_saturates() {
+        let max = NearAmount::from_yocto(u128::MAX);
+        assert_eq!(max + NearAmount::from_yocto(1), max);
+        assert_eq!(
+            NearAmount::from_yocto(1).saturating_sub(NearAmount::from_yocto(2)),
+            NearAmount::ZERO
+        );
+        assert_eq!(
+            NearAmount::from_yocto(999).percent(&decimal("50")),
+            NearAmount::from_yocto(499)
+        );
+    }
+
+    #[test]
+    fn parse_human_amount() {
+        assert_eq!("all".parse(), Ok(HumanAmount::All));
+        assert_eq!(" MAX ".parse(), Ok(HumanAmount::All));
+        assert_eq!("1.5".parse(), Ok(HumanAmount::Exact(decimal("1.5"))));
+        assert!("-1".parse::<HumanAmount>().is_err());
+        assert!("50%".parse::<HumanAmount>().is_err());
+        assert!("abc".parse::<HumanAmount>().is_err());
+        assert_eq!(".5".parse(), Ok(HumanAmount::Exact(decimal("0.5"))));
+        assert!(".".parse::<HumanAmount>().is_err());
+        assert!("1.2.3".parse::<HumanAmount>().is_err());
+    }
+
+    #[test]
+    fn reject_exponents() {
+        for amount in ["1e999999999", "1e-999999999", "1E5", "1.5e3", "1e+2"] {
+            assert_eq!(
+                amount.parse::<HumanAmount>(),
+                Err(format!("Invalid amount: {amount}"))
+            );
+            assert!(amount.parse::<BalanceAmount>().is_err());
+            assert!(format!("{amount}%").parse::<BalanceAmount>().is_err());
+        }
+        let long = "1".repeat(MAX_AMOUNT_LENGTH + 1);
+        assert!(long.parse::<HumanAmount>().is_err());
+        let long = format!("0.{}", "0".repeat(MAX_AMOUNT_LENGTH));
+        assert!(long.parse::<HumanAmount>().is_err());
+    }
+
+    #[test]
+    fn parse_balance_amount() {
+        assert_eq!("all".parse(), Ok(BalanceAmount::All));
+        assert_eq!("max".parse(), Ok(BalanceAmount::All));
+        assert_eq!("50%".parse(), Ok(BalanceAmount::Percent(decimal("50"))));
+        assert_eq!("100 %".parse(), Ok(BalanceAmount::Percent(decimal("100"))));
+        assert_eq!("2".parse(), Ok(BalanceAmount::Exact(decimal("2"))));
+        assert!("101%".parse::<BalanceAmount>().is_err());
+        assert!("0%".parse::<BalanceAmount>().is_err());
+        assert!("x%".parse::<BalanceAmount>().is_err());
+    }
+}
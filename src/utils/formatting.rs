@@ -1,51 +1,53 @@
+use std::time::Duration;
+
+use bigdecimal::{BigDecimal, RoundingMode, Zero};
 use near_primitives::types::AccountId;
+use num_traits::ToPrimitive;
+
+use crate::global_state::{get_ft_price, get_near_price, TokenMetadataWithoutIcon};
 
-use crate::global_state::{get_ft_metadata, get_ft_price};
+use super::amount::{to_plain_string, NearAmount, TokenAmount};
 
 pub const NEAR_DECIMALS: u32 = 24;
 pub const WRAP_NEAR: &str = "wrap.near";
 
-pub async fn format_near_amount(amount: u128) -> String {
-    if amount == 0 {
+pub async fn format_near_amount(amount: NearAmount) -> String {
+    if amount.is_zero() {
         "0 NEAR".to_string()
-    } else if amount < 10u128.pow(18) {
-        format!("{amount} yoctoNEAR")
+    } else if amount.as_yocto() < 10u128.pow(18) {
+        format!("{} yoctoNEAR", amount.as_yocto())
     } else {
         format!(
             "{}{}",
-            format_token_amount(amount, NEAR_DECIMALS, "NEAR"),
-            if amount != 0 {
-                format!(
-                    " (${:.02})",
-                    (amount as f64 / 10u128.pow(NEAR_DECIMALS) as f64)
-                        * get_ft_price(&WRAP_NEAR.parse().unwrap())
-                            .await
-                            .unwrap_or_default()
-                )
-            } else {
-                "".to_string()
+            amount.as_token_amount().format("NEAR"),
+            match get_near_price().await {
+                Some(price) => format_usd_value(&amount.as_token_amount().usd_value(&price)),
+                None => "".to_string(),
             }
         )
     }
 }
 
-pub async fn format_tokens(amount: u128, token: &AccountId) -> String {
-    if let Some(metadata) = get_ft_metadata(token).await {
+/// `metadata` is from `resolve_ft_metadata`, the raw amount is shown without it
+pub async fn format_tokens(
+    amount: u128,
+    token: &AccountId,
+    metadata: Option<&TokenMetadataWithoutIcon>,
+) -> String {
+    if let Some(metadata) = metadata {
+        let amount = TokenAmount::from_raw(amount, metadata.decimals);
         format!(
             "{}{}",
-            format_token_amount(amount, metadata.decimals, &metadata.symbol),
-            if amount != 0 {
+            amount.format(&metadata.symbol),
+            if !amount.is_zero() {
                 if let Some(price) = get_ft_price(token).await {
-                    if price != 0f64 {
-                        format!(
-                            " (${:.02})",
-                            (amount as f64 / 10u128.pow(metadata.decimals) as f64) * price
-                        )
+                    if !price.is_zero() {
+                        format_usd_value(&amount.usd_value(&price))
                     } else {
                         "".to_string()
                     }
                 } else {
-                    "".to_string()
+                    " (no price)".to_string()
                 }
             } else {
                 "".to_string()
@@ -56,41 +58,49 @@ pub async fn format_tokens(amount: u128, token: &AccountId) -> String {
     }
 }
 
-pub fn format_token_amount(amount: u128, decimals: u32, symbol: &str) -> String {
-    if decimals == 0 {
-        return format!("{amount} {symbol}");
-    }
-    if amount == 0 {
-        return format!("0 {symbol}");
+/// ` ($1.23)` suffix for an amount of tokens
+fn format_usd_value(value: &BigDecimal) -> String {
+    format!(" ({})", format_usd_cents(value))
+}
+
+/// `$1.23`, for totals and values of holdings
+pub fn format_usd_cents(value: &BigDecimal) -> String {
+    format!("${}", value.with_scale_round(2, RoundingMode::HalfUp))
+}
+
+pub fn format_usd_amount(amount: &BigDecimal) -> String {
+    if amount.is_zero() {
+        return "$0".to_string();
     }
-    let precision = 12.min(decimals);
-    let token_float: f64 = (amount / 10u128.pow(decimals - precision)) as f64
-        / (10u128.pow(decimals) / 10u128.pow(decimals - precision)) as f64;
-    let s = if token_float >= 1_000_000.0 {
-        format!("{token_float:.0}")
-    } else if token_float >= 10.0 {
-        format!("{token_float:.2}")
-    } else if token_float >= 1.0 {
-        format!("{token_float:.3}")
-    } else if token_float >= 1.0 / 1e12 {
-        let digits = -token_float.abs().log10().floor() as usize + 2;
-        format!("{token_float:.*}", digits)
-    } else {
-        "0".to_string()
-    };
+    let magnitude = amount.to_f64().unwrap_or_default().log10() as i64;
     format!(
-        "{amount} {symbol}",
-        amount = if s.contains('.') {
-            s.trim_end_matches('0').trim_end_matches('.')
-        } else {
-            &s
-        }
+        "${}",
+        to_plain_string(&amount.with_scale_round((2 - magnitude).max(0), RoundingMode::HalfUp))
     )
 }
 
-pub fn format_usd_amount(amount: f64) -> String {
-    format!(
-        "${amount:.0$}",
-        (2 - amount.log10() as isize).max(0) as usize
-    )
+/// `5 minutes`, `3 hours`, `2 days 1 hour`
+pub fn format_duration(duration: Duration) -> String {
+    let plural = |count: u64, unit: &str| {
+        if count == 1 {
+            format!("1 {unit}")
+        } else {
+            format!("{count} {unit}s")
+        }
+    };
+    let minutes = duration.as_secs() / 60;
+    let hours = minutes / 60;
+    if hours == 0 {
+        plural(minutes.max(1), "minute")
+    } else if hours < 24 {
+        plural(hours, "hour")
+    } else if hours.is_multiple_of(24) {
+        plural(hours / 24, "day")
+    } else {
+        format!(
+            "{} {}",
+            plural(hours / 24, "day"),
+            plural(hours % 24, "hour")
+        )
+    }
 }
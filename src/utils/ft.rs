@@ -0,0 +1,94 @@
+use cached::proc_macro::cached;
+use near_primitives::types::AccountId;
+use serde::Deserialize;
+
+use crate::{
+    config::CONFIG,
+    global_state::{get_ft_metadata, TokenMetadataWithoutIcon},
+};
+
+use super::{amount::NearAmount, rpc::view_cached_30s, rpc::RpcError};
+
+#[derive(Debug, Deserialize)]
+struct StorageBalanceBounds {
+    min: NearAmount,
+}
+
+pub async fn ft_balance_of(
+    contract_id: &AccountId,
+    account_id: &AccountId,
+) -> Result<u128, RpcError> {
+    view_cached_30s::<_, String>(
+        contract_id,
+        "ft_balance_of",
+        serde_json::json!({ "account_id": account_id }),
+    )
+    .await?
+    .parse()
+    .map_err(|err| {
+        RpcError::InvalidResult(anyhow::anyhow!(
+            "ft_balance_of of {contract_id} is not a number: {err}"
+        ))
+    })
+}
+
+/// Metadata from the token list, or from the contract if the token isn't
+/// listed. `None` if the contract doesn't return valid metadata.
+pub async fn resolve_ft_metadata(token: &AccountId) -> Option<TokenMetadataWithoutIcon> {
+    if let Some(metadata) = get_ft_metadata(token).await {
+        return Some(metadata);
+    }
+    match ft_metadata_cached(token.clone()).await {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            log::debug!("Failed to get ft_metadata of {token}: {err:?}");
+            None
+        }
+    }
+}
+
+/// Metadata rarely changes, so it's kept much longer than other view results.
+/// `None` if the contract is not a fungible token, which is cached too, so
+/// that NFTs and other contracts in the token list aren't called every time.
+/// Errors that another request might not have are not cached.
+#[cached(
+    ty = "cached::TimedSizedCache<AccountId, Option<TokenMetadataWithoutIcon>>",
+    create = "{ cached::TimedSizedCache::with_size_and_lifespan(10000, CONFIG.cache.ft_metadata_ttl_secs) }",
+    result = true
+)]
+pub async fn ft_metadata_cached(
+    token: AccountId,
+) -> Result<Option<TokenMetadataWithoutIcon>, RpcError> {
+    match view_cached_30s(&token, "ft_metadata", serde_json::json!({})).await {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(err) if !err.is_retryable() => {
+            log::debug!("{token} is not a fungible token: {err:?}");
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Deposit needed to register `account_id` on the token contract, `None` if
+/// it's already registered
+pub async fn storage_deposit_needed(
+    contract_id: &AccountId,
+    account_id: &AccountId,
+) -> Result<Option<NearAmount>, RpcError> {
+    let storage_balance = view_cached_30s::<_, Option<serde_json::Value>>(
+        contract_id,
+        "storage_balance_of",
+        serde_json::json!({ "account_id": account_id }),
+    )
+    .await?;
+    if storage_balance.is_some() {
+        return Ok(None);
+    }
+    let bounds = view_cached_30s::<_, StorageBalanceBounds>(
+        contract_id,
+        "storage_balance_bounds",
+        serde_json::json!({}),
+    )
+    .await?;
+    Ok(Some(bounds.min))
+}
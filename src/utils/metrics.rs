@@ -0,0 +1,144 @@
+use std::{sync::Mutex, time::Duration};
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, Gauge, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+use crate::{endpoints::find_operation, global_state::get_tokens_age};
+
+use super::rpc::get_cache_stats;
+
+/// Routes that aren't operations, by first path segment
+const OTHER_ROUTES: &[&str] = &["healthz", "readyz", "version", "metrics", ".well-known"];
+
+lazy_static! {
+    static ref CACHE_COUNTERS_LOCK: Mutex<()> = Mutex::new(());
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests by route and status code",
+        &["route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency by route",
+        &["route"]
+    )
+    .unwrap();
+    static ref RPC_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "rpc_requests_total",
+        "Number of NEAR RPC requests by RPC URL and result",
+        &["url", "result"]
+    )
+    .unwrap();
+    static ref RPC_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "rpc_request_duration_seconds",
+        "NEAR RPC request latency by RPC URL",
+        &["url"]
+    )
+    .unwrap();
+    static ref CACHE_HITS: IntCounterVec = register_int_counter_vec!(
+        "cache_hits_total",
+        "Number of hits of in-memory caches",
+        &["cache"]
+    )
+    .unwrap();
+    static ref CACHE_MISSES: IntCounterVec = register_int_counter_vec!(
+        "cache_misses_total",
+        "Number of misses of in-memory caches",
+        &["cache"]
+    )
+    .unwrap();
+    static ref CACHE_SIZE: IntGaugeVec = register_int_gauge_vec!(
+        "cache_size",
+        "Number of entries in in-memory caches",
+        &["cache"]
+    )
+    .unwrap();
+    static ref TOKEN_CACHE_AGE: Gauge = register_gauge!(
+        "token_cache_age_seconds",
+        "Time since the token list was last refreshed, -1 if it was never loaded"
+    )
+    .unwrap();
+}
+
+pub fn record_request(path: &str, status: warp::http::StatusCode, elapsed: Duration) {
+    let route = route_label(path);
+    HTTP_REQUESTS
+        .with_label_values(&[route, status.as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[route])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Only the first path segment of known routes is used as the route, other
+/// paths are grouped as `other`, so that random paths don't create new time
+/// series
+fn route_label(path: &str) -> &str {
+    let route = path
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    if find_operation(route).is_some() || OTHER_ROUTES.contains(&route) {
+        route
+    } else {
+        "other"
+    }
+}
+
+/// `outcome` is `ok`, `error` for RPC failures, or `request_error` for
+/// errors caused by the request, like a nonexistent account
+pub fn record_rpc_request(url: &str, elapsed: Duration, outcome: &str) {
+    RPC_REQUESTS.with_label_values(&[url, outcome]).inc();
+    RPC_REQUEST_DURATION
+        .with_label_values(&[url])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Updates the metrics that are read from other state, and encodes all
+/// metrics in Prometheus text format
+pub async fn gather() -> Result<String, anyhow::Error> {
+    let cache_stats = get_cache_stats().await;
+    {
+        // The caches count since startup, counters only go up by the
+        // difference. Locked so that concurrent scrapes don't both add it.
+        let _lock = CACHE_COUNTERS_LOCK.lock().unwrap();
+        for stats in cache_stats {
+            let hits = CACHE_HITS.with_label_values(&[stats.name]);
+            hits.inc_by(stats.hits.saturating_sub(hits.get()));
+            let misses = CACHE_MISSES.with_label_values(&[stats.name]);
+            misses.inc_by(stats.misses.saturating_sub(misses.get()));
+            CACHE_SIZE
+                .with_label_values(&[stats.name])
+                .set(stats.size as i64);
+        }
+    }
+    TOKEN_CACHE_AGE.set(
+        get_tokens_age()
+            .await
+            .map(|age| age.as_secs_f64())
+            .unwrap_or(-1.0),
+    );
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_labels() {
+        assert_eq!(route_label("/stake"), "stake");
+        assert_eq!(route_label("/healthz"), "healthz");
+        assert_eq!(route_label("/.well-known/ai-plugin.json"), ".well-known");
+        assert_eq!(route_label("/wp-admin/index.php"), "other");
+        assert_eq!(route_label("/"), "other");
+    }
+}
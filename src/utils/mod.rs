@@ -1,2 +1,10 @@
+pub mod amount;
 pub mod formatting;
+pub mod ft;
+pub mod metrics;
+pub mod query;
+pub mod ref_finance;
+pub mod response;
 pub mod rpc;
+pub mod transaction;
+pub mod validators;
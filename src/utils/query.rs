@@ -0,0 +1,17 @@
+use serde::de::DeserializeOwned;
+use warp::{Filter, Rejection};
+
+use crate::error::AgentError;
+
+/// Same as `warp::query::query`, but rejects with the deserialization error
+/// message, so that the LLM knows which parameter is wrong
+pub fn query<T: DeserializeOwned + Send + 'static>(
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|query: String| async move {
+            serde_urlencoded::from_str::<T>(&query)
+                .map_err(|err| Rejection::from(AgentError::InvalidInput(err.to_string())))
+        })
+}
@@ -0,0 +1,462 @@
+//! Ref Finance pools and swap routing. Route computation only depends on the
+//! pool data passed to it, loading the pools is separate.
+
+use std::{cmp::Reverse, collections::HashMap, sync::Arc};
+
+use bigdecimal::{num_bigint::BigUint, BigDecimal, One, ToPrimitive, Zero};
+use cached::proc_macro::cached;
+use near_primitives::serialize::dec_format;
+use near_primitives::types::AccountId;
+use serde::{Deserialize, Serialize};
+
+use super::rpc::{view_cached_30s, RpcError};
+
+pub const REF_FINANCE_CONTRACT: &str = "v2.ref-finance.near";
+
+/// Fees are in basis points of this
+const FEE_DIVISOR: u32 = 10_000;
+/// Pools are loaded in pages of this size
+const POOLS_PAGE_SIZE: u64 = 250;
+/// Total reserves are only used to rank tokens, so they don't need to be as
+/// fresh as the pools used for quotes
+const TOTAL_RESERVES_TTL_SECS: u64 = 5 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pub id: u64,
+    pub tokens: Vec<AccountId>,
+    pub reserves: Vec<u128>,
+    pub fee_bps: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hop {
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    #[serde(with = "dec_format")]
+    pub amount_in: u128,
+    #[serde(with = "dec_format")]
+    pub amount_out: u128,
+}
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub hops: Vec<Hop>,
+    /// How much worse the output is than at the current pool prices after
+    /// fees, from 0 to 1
+    pub price_impact: BigDecimal,
+}
+
+impl Route {
+    pub fn amount_in(&self) -> u128 {
+        self.hops.first().map_or(0, |hop| hop.amount_in)
+    }
+
+    pub fn amount_out(&self) -> u128 {
+        self.hops.last().map_or(0, |hop| hop.amount_out)
+    }
+}
+
+impl Pool {
+    fn token_index(&self, token: &AccountId) -> Option<usize> {
+        self.tokens.iter().position(|t| t == token)
+    }
+
+    /// Same formula as the Ref Finance contract uses for simple pools
+    pub fn swap_output(&self, token_in: usize, token_out: usize, amount_in: u128) -> u128 {
+        let amount_with_fee = BigUint::from(amount_in) * (FEE_DIVISOR - self.fee_bps);
+        let amount_out = &amount_with_fee * self.reserves[token_out]
+            / (BigUint::from(self.reserves[token_in]) * FEE_DIVISOR + &amount_with_fee);
+        // Always less than the reserve, so it fits
+        amount_out.to_u128().unwrap_or_default()
+    }
+
+    fn hop(&self, token_in: usize, token_out: usize, amount_in: u128) -> Hop {
+        Hop {
+            pool_id: self.id,
+            token_in: self.tokens[token_in].clone(),
+            token_out: self.tokens[token_out].clone(),
+            amount_in,
+            amount_out: self.swap_output(token_in, token_out, amount_in),
+        }
+    }
+
+    /// Output for 1 unit of input at the current price after fees
+    fn spot_rate(&self, token_in: usize, token_out: usize) -> BigDecimal {
+        BigDecimal::from(self.reserves[token_out]) / BigDecimal::from(self.reserves[token_in])
+            * BigDecimal::from(FEE_DIVISOR - self.fee_bps)
+            / BigDecimal::from(FEE_DIVISOR)
+    }
+}
+
+/// The route with the highest output, direct or through one intermediate
+/// token, or `None` if the tokens can't be swapped
+pub fn best_route(
+    pools: &[Pool],
+    token_in: &AccountId,
+    token_out: &AccountId,
+    amount_in: u128,
+) -> Option<Route> {
+    if amount_in == 0 || token_in == token_out {
+        return None;
+    }
+    let mut candidates: Vec<Vec<(&Pool, Hop)>> = Vec::new();
+
+    // Best two first hops for each token reachable from `token_in`. The
+    // second hop output only grows with its input, so the best first hop is
+    // part of the best route through that token, unless the second hop is in
+    // the same pool, then it's the runner-up.
+    let mut first_hops: HashMap<&AccountId, Vec<(&Pool, Hop)>> = HashMap::new();
+    for pool in pools {
+        let Some(in_index) = pool.token_index(token_in) else {
+            continue;
+        };
+        for (out_index, token) in pool.tokens.iter().enumerate() {
+            if out_index == in_index {
+                continue;
+            }
+            let hop = pool.hop(in_index, out_index, amount_in);
+            if token == token_out {
+                candidates.push(vec![(pool, hop)]);
+            } else {
+                let hops = first_hops.entry(token).or_default();
+                hops.push((pool, hop));
+                hops.sort_by_key(|(_, hop)| Reverse(hop.amount_out));
+                hops.truncate(2);
+            }
+        }
+    }
+
+    for pool in pools {
+        let Some(out_index) = pool.token_index(token_out) else {
+            continue;
+        };
+        for (mid_index, token) in pool.tokens.iter().enumerate() {
+            let Some((first_pool, first_hop)) = first_hops
+                .get(token)
+                .and_then(|hops| hops.iter().find(|(first_pool, _)| first_pool.id != pool.id))
+            else {
+                continue;
+            };
+            if first_hop.amount_out == 0 {
+                continue;
+            }
+            let second_hop = pool.hop(mid_index, out_index, first_hop.amount_out);
+            candidates.push(vec![(*first_pool, first_hop.clone()), (pool, second_hop)]);
+        }
+    }
+
+    let hops = candidates
+        .into_iter()
+        .filter(|hops| hops.last().is_some_and(|(_, hop)| hop.amount_out > 0))
+        .max_by_key(|hops| hops.last().map(|(_, hop)| hop.amount_out))?;
+    let ideal_amount_out = hops
+        .iter()
+        .fold(BigDecimal::from(amount_in), |amount, (pool, hop)| {
+            let token_in = pool.token_index(&hop.token_in).unwrap();
+            let token_out = pool.token_index(&hop.token_out).unwrap();
+            amount * pool.spot_rate(token_in, token_out)
+        });
+    let amount_out = BigDecimal::from(hops.last()?.1.amount_out);
+    let price_impact = if ideal_amount_out.is_zero() {
+        BigDecimal::one()
+    } else {
+        (BigDecimal::one() - amount_out / ideal_amount_out).max(BigDecimal::zero())
+    };
+    Some(Route {
+        hops: hops.into_iter().map(|(_, hop)| hop).collect(),
+        price_impact,
+    })
+}
+
+/// Reserves of each token summed over all pools, in the token's raw units
+fn total_reserves(pools: &[Pool]) -> HashMap<AccountId, u128> {
+    let mut reserves: HashMap<AccountId, u128> = HashMap::new();
+    for pool in pools {
+        for (token, reserve) in pool.tokens.iter().zip(&pool.reserves) {
+            let total = reserves.entry(token.clone()).or_default();
+            *total = total.saturating_add(*reserve);
+        }
+    }
+    reserves
+}
+
+/// `msg` of `ft_transfer_call` to Ref Finance that swaps along `route`
+pub fn swap_msg(route: &Route, min_amount_out: u128) -> String {
+    let last = route.hops.len() - 1;
+    let actions = route
+        .hops
+        .iter()
+        .enumerate()
+        .map(|(i, hop)| {
+            let mut action = serde_json::json!({
+                "pool_id": hop.pool_id,
+                "token_in": hop.token_in,
+                "token_out": hop.token_out,
+                // Only the last hop's output matters
+                "min_amount_out": if i == last { min_amount_out.to_string() } else { "0".to_string() },
+            });
+            // Next hops use the output of the previous one
+            if i == 0 {
+                action["amount_in"] = hop.amount_in.to_string().into();
+            }
+            action
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({ "force": 0, "actions": actions }).to_string()
+}
+
+/// `total_reserves` of all pools, cached so that token searches don't scan
+/// every pool
+#[cached(
+    ty = "cached::TimedCache<(), Arc<HashMap<AccountId, u128>>>",
+    create = "{ cached::TimedCache::with_lifespan(TOTAL_RESERVES_TTL_SECS) }",
+    result = true
+)]
+pub async fn get_total_reserves() -> Result<Arc<HashMap<AccountId, u128>>, RpcError> {
+    Ok(Arc::new(total_reserves(&get_pools().await?)))
+}
+
+#[derive(Debug, Deserialize)]
+struct PoolInfo {
+    pool_kind: String,
+    token_account_ids: Vec<AccountId>,
+    amounts: Vec<String>,
+    total_fee: u32,
+}
+
+/// Simple pools with liquidity. Stable pools use a different formula and
+/// are skipped.
+pub async fn get_pools() -> Result<Vec<Pool>, RpcError> {
+    let count: u64 = view_cached_30s(
+        REF_FINANCE_CONTRACT,
+        "get_number_of_pools",
+        serde_json::json!({}),
+    )
+    .await?;
+    let pages = futures_util::future::join_all((0..count).step_by(POOLS_PAGE_SIZE as usize).map(
+        |from_index| async move {
+            view_cached_30s::<_, Vec<PoolInfo>>(
+                REF_FINANCE_CONTRACT,
+                "get_pools",
+                serde_json::json!({ "from_index": from_index, "limit": POOLS_PAGE_SIZE }),
+            )
+            .await
+            .map(|pools| (from_index, pools))
+        },
+    ))
+    .await;
+
+    let mut pools = Vec::new();
+    for page in pages {
+        let (from_index, page) = page?;
+        for (i, pool) in page.into_iter().enumerate() {
+            if pool.pool_kind != "SIMPLE_POOL" || pool.total_fee >= FEE_DIVISOR {
+                continue;
+            }
+            let reserves = pool
+                .amounts
+                .iter()
+                .map(|amount| amount.parse().unwrap_or_default())
+                .collect::<Vec<u128>>();
+            if reserves.len() != pool.token_account_ids.len() || reserves.contains(&0) {
+                continue;
+            }
+            pools.push(Pool {
+                id: from_index + i as u64,
+                tokens: pool.token_account_ids,
+                reserves,
+                fee_bps: pool.total_fee,
+            });
+        }
+    }
+    Ok(pools)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn token(id: &str) -> AccountId {
+        id.parse().unwrap()
+    }
+
+    fn pool(id: u64, tokens: [&str; 2], reserves: [u128; 2], fee_bps: u32) -> Pool {
+        Pool {
+            id,
+            tokens: tokens.into_iter().map(token).collect(),
+            reserves: reserves.to_vec(),
+            fee_bps,
+        }
+    }
+
+    #[test]
+    fn swap_output_fee() {
+        // amount_in * (FEE_DIVISOR - fee) * reserve_out
+        //     / (reserve_in * FEE_DIVISOR + amount_in * (FEE_DIVISOR - fee))
+        let pool = pool(0, ["a.near", "b.near"], [1_000_000, 2_000_000], 30);
+        assert_eq!(
+            pool.swap_output(0, 1, 1000),
+            1000 * 9970 * 2_000_000 / (1_000_000 * 10_000 + 1000 * 9970)
+        );
+        assert_eq!(pool.swap_output(0, 1, 1000), 1992);
+        assert_eq!(pool.swap_output(1, 0, 1000), 498);
+
+        let no_fee = Pool { fee_bps: 0, ..pool };
+        assert_eq!(no_fee.swap_output(0, 1, 1000), 1998);
+    }
+
+    #[test]
+    fn direct_route() {
+        let pools = [
+            pool(0, ["a.near", "b.near"], [1_000_000, 1_000_000], 30),
+            pool(1, ["a.near", "c.near"], [1_000_000, 1_000_000], 30),
+        ];
+        let route = best_route(&pools, &token("a.near"), &token("b.near"), 1000).unwrap();
+        assert_eq!(route.hops.len(), 1);
+        assert_eq!(route.hops[0].pool_id, 0);
+        assert_eq!(route.amount_in(), 1000);
+        assert_eq!(route.amount_out(), pools[0].swap_output(0, 1, 1000));
+        // Only the fee and a tiny price impact
+        assert!(route.price_impact < BigDecimal::from_str("0.01").unwrap());
+    }
+
+    #[test]
+    fn two_hop_route_beats_direct() {
+        let pools = [
+            // Shallow direct pool
+            pool(0, ["a.near", "b.near"], [1000, 1000], 30),
+            pool(1, ["a.near", "c.near"], [1_000_000, 1_000_000], 30),
+            pool(2, ["c.near", "b.near"], [1_000_000, 1_000_000], 30),
+        ];
+        let direct = pools[0].swap_output(0, 1, 1000);
+        let route = best_route(&pools, &token("a.near"), &token("b.near"), 1000).unwrap();
+        assert_eq!(
+            route.hops.iter().map(|hop| hop.pool_id).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(route.hops[0].token_out, token("c.near"));
+        assert_eq!(route.hops[1].amount_in, route.hops[0].amount_out);
+        assert_eq!(
+            route.amount_out(),
+            pools[2].swap_output(0, 1, pools[1].swap_output(0, 1, 1000))
+        );
+        assert!(route.amount_out() > direct);
+    }
+
+    /// Best output over all direct and two-hop routes through different pools
+    fn brute_force_best(pools: &[Pool], token_in: &str, token_out: &str, amount_in: u128) -> u128 {
+        let (token_in, token_out) = (token(token_in), token(token_out));
+        let mut best = 0;
+        for first in pools {
+            let Some(in_index) = first.token_index(&token_in) else {
+                continue;
+            };
+            for (mid_index, mid) in first.tokens.iter().enumerate() {
+                if mid_index == in_index {
+                    continue;
+                }
+                let mid_amount = first.swap_output(in_index, mid_index, amount_in);
+                if *mid == token_out {
+                    best = best.max(mid_amount);
+                    continue;
+                }
+                for second in pools.iter().filter(|second| second.id != first.id) {
+                    if let (Some(mid_index), Some(out_index)) =
+                        (second.token_index(mid), second.token_index(&token_out))
+                    {
+                        best = best.max(second.swap_output(mid_index, out_index, mid_amount));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn shared_best_pool() {
+        // Pool 0 is the best pool for a -> c and the only one for c -> b, so
+        // routes through c use pool 1 first
+        let pools = [
+            Pool {
+                id: 0,
+                tokens: ["a.near", "c.near", "b.near"].map(token).to_vec(),
+                reserves: vec![1_000_000, 2_000_000, 1_000_000],
+                fee_bps: 30,
+            },
+            pool(1, ["a.near", "c.near"], [1_000_000, 1_900_000], 5),
+            pool(2, ["a.near", "d.near"], [1_000_000, 1_000_000], 30),
+            pool(3, ["d.near", "b.near"], [1_000_000, 1_100_000], 30),
+        ];
+        for amount_in in [1000, 100_000, 900_000] {
+            let route = best_route(&pools, &token("a.near"), &token("b.near"), amount_in).unwrap();
+            assert_eq!(
+                route.amount_out(),
+                brute_force_best(&pools, "a.near", "b.near", amount_in)
+            );
+            if let [first, second] = route.hops.as_slice() {
+                assert_ne!(first.pool_id, second.pool_id);
+            }
+        }
+    }
+
+    #[test]
+    fn total_reserves_of_tokens() {
+        let pools = [
+            pool(0, ["a.near", "b.near"], [100, 200], 30),
+            pool(1, ["a.near", "c.near"], [u128::MAX, 300], 30),
+            pool(2, ["c.near", "b.near"], [400, 500], 30),
+        ];
+        let reserves = total_reserves(&pools);
+        assert_eq!(reserves[&token("a.near")], u128::MAX);
+        assert_eq!(reserves[&token("b.near")], 700);
+        assert_eq!(reserves[&token("c.near")], 700);
+        assert_eq!(reserves.len(), 3);
+    }
+
+    #[test]
+    fn no_route() {
+        let pools = [
+            pool(0, ["a.near", "b.near"], [1_000_000, 1_000_000], 30),
+            pool(1, ["c.near", "d.near"], [1_000_000, 1_000_000], 30),
+        ];
+        assert!(best_route(&pools, &token("a.near"), &token("d.near"), 1000).is_none());
+        assert!(best_route(&pools, &token("a.near"), &token("e.near"), 1000).is_none());
+        assert!(best_route(&pools, &token("a.near"), &token("a.near"), 1000).is_none());
+        assert!(best_route(&pools, &token("a.near"), &token("b.near"), 0).is_none());
+    }
+
+    #[test]
+    fn swap_msg_amounts() {
+        let pools = [
+            pool(0, ["a.near", "c.near"], [1_000_000, 1_000_000], 30),
+            pool(1, ["c.near", "b.near"], [1_000_000, 1_000_000], 30),
+        ];
+        let route = best_route(&pools, &token("a.near"), &token("b.near"), 1000).unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&swap_msg(&route, 900)).unwrap();
+        assert_eq!(
+            msg,
+            serde_json::json!({
+                "force": 0,
+                "actions": [
+                    {
+                        "pool_id": 0,
+                        "token_in": "a.near",
+                        "token_out": "c.near",
+                        "amount_in": "1000",
+                        "min_amount_out": "0",
+                    },
+                    {
+                        "pool_id": 1,
+                        "token_in": "c.near",
+                        "token_out": "b.near",
+                        "min_amount_out": "900",
+                    },
+                ],
+            })
+        );
+    }
+}
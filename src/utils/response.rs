@@ -0,0 +1,94 @@
+use bigdecimal::{BigDecimal, RoundingMode};
+use near_primitives::serialize::dec_format;
+use serde::{Deserialize, Serialize};
+use warp::{Filter, Rejection};
+
+use super::amount::{to_plain_string, NearAmount, TokenAmount};
+
+/// Text is written for the LLM and is the default. JSON is returned for
+/// `?format=json`, or when the client accepts `application/json` but not
+/// `text/plain`, so that HTTP clients that accept everything (like the
+/// agent runtime) still get text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+pub fn response_format() -> impl Filter<Extract = (ResponseFormat,), Error = Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and(warp::header::optional::<String>("accept"))
+        .map(|query: String, accept: Option<String>| {
+            let format = serde_urlencoded::from_str::<FormatQuery>(&query)
+                .ok()
+                .and_then(|query| query.format);
+            match format.as_deref() {
+                Some("json") => ResponseFormat::Json,
+                Some("text") => ResponseFormat::Text,
+                _ => accept.map_or(ResponseFormat::Text, |accept| format_from_accept(&accept)),
+            }
+        })
+}
+
+fn format_from_accept(accept: &str) -> ResponseFormat {
+    let media_types = accept
+        .split(',')
+        .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+        .collect::<Vec<_>>();
+    if media_types.contains(&"application/json")
+        && !media_types.contains(&"text/plain")
+        && !media_types.contains(&"text/*")
+    {
+        ResponseFormat::Json
+    } else {
+        ResponseFormat::Text
+    }
+}
+
+/// Balance of NEAR or a fungible token in JSON responses. Amounts and prices
+/// are strings to avoid losing precision.
+#[derive(Debug, Serialize)]
+pub struct BalanceJson {
+    /// In the smallest unit of the token, yoctoNEAR for NEAR
+    #[serde(with = "dec_format")]
+    pub raw: u128,
+    /// `null` if token metadata is unknown
+    pub decimals: Option<u32>,
+    /// `raw` divided by 10^decimals, e.g. "1.5". `null` if decimals are unknown
+    pub amount: Option<String>,
+    /// `null` if the price is unknown
+    pub price_usd: Option<String>,
+    /// Rounded to cents. `null` if the price is unknown
+    pub usd_value: Option<String>,
+}
+
+impl BalanceJson {
+    pub fn new(raw: u128, decimals: Option<u32>, price_usd: Option<&BigDecimal>) -> Self {
+        let amount = decimals.map(|decimals| TokenAmount::from_raw(raw, decimals));
+        Self {
+            raw,
+            decimals,
+            amount: amount.map(|amount| amount.to_string()),
+            price_usd: price_usd.map(to_plain_string),
+            usd_value: amount.zip(price_usd).map(|(amount, price_usd)| {
+                to_plain_string(
+                    &amount
+                        .usd_value(price_usd)
+                        .with_scale_round(2, RoundingMode::HalfUp),
+                )
+            }),
+        }
+    }
+
+    pub fn near(amount: NearAmount, price_usd: Option<&BigDecimal>) -> Self {
+        let amount = amount.as_token_amount();
+        Self::new(amount.raw(), Some(amount.decimals()), price_usd)
+    }
+}
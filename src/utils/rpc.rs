@@ -1,33 +1,46 @@
 use base64::Engine;
-use cached::proc_macro::cached;
+use cached::{proc_macro::cached, Cached};
 use lazy_static::lazy_static;
-use near_primitives::serialize::dec_format;
 use near_primitives::types::AccountId;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-pub const RPC_URLS: &[&str] = &[
-    "https://rpc.intear.tech",
-    "https://rpc.shitzuapes.xyz",
-    "https://rpc.mainnet.near.org",
-    "https://near.lava.build",
-];
+use crate::config::CONFIG;
+
+use super::{
+    amount::NearAmount,
+    ft::FT_METADATA_CACHED,
+    metrics::record_rpc_request,
+    validators::{get_protocol_config, GET_POOL_FEE},
+};
 
 macro_rules! try_rpc {
     (|$rpc_url: ident| $body: block) => {{
         let mut i = 0;
         loop {
             let result: Result<_, _> = async {
-                let $rpc_url = RPC_URLS[i];
-                let res = $body;
+                let $rpc_url = CONFIG.rpc_urls[i].as_str();
+                let start = std::time::Instant::now();
+                let res: Result<_, RpcError> = $body;
+                let outcome = match &res {
+                    Ok(_) => "ok",
+                    Err(err) if err.is_retryable() => "error",
+                    Err(_) => "request_error",
+                };
+                record_rpc_request($rpc_url, start.elapsed(), outcome);
                 res
             }
             .await;
             match result {
                 Ok(res) => break Ok(res),
                 Err(err) => {
-                    if i >= RPC_URLS.len() - 1 {
+                    // Errors like nonexistent account would be the same on every RPC
+                    if !err.is_retryable() || i >= CONFIG.rpc_urls.len() - 1 {
                         break Err(err);
                     }
+                    log::warn!(
+                        "RPC {} failed, trying the next one: {err}",
+                        CONFIG.rpc_urls[i]
+                    );
                     i += 1;
                 }
             }
@@ -36,12 +49,145 @@ macro_rules! try_rpc {
 }
 
 #[derive(Deserialize, Debug)]
-pub struct RpcResponse<T> {
+struct RpcResponse<T> {
     #[allow(dead_code)]
     id: Option<String>,
     #[allow(dead_code)]
     jsonrpc: String,
-    result: T,
+    result: Option<T>,
+    error: Option<RpcErrorResponse>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcErrorResponse {
+    name: Option<String>,
+    cause: Option<RpcErrorCause>,
+    message: String,
+    data: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcErrorCause {
+    name: String,
+    #[serde(default)]
+    info: serde_json::Value,
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    /// Couldn't connect, got a 5xx, or the response is not valid JSON-RPC
+    Transport(anyhow::Error),
+    RateLimited,
+    Timeout,
+    /// The node doesn't have the requested block, usually because it's behind
+    UnknownBlock,
+    UnknownAccount(String),
+    NoContractCode(String),
+    ContractExecution(String),
+    /// The contract call succeeded, but returned something unexpected
+    InvalidResult(anyhow::Error),
+    /// The request is malformed, `PARSE_ERROR` from the node, or its
+    /// arguments couldn't be serialized
+    InvalidRequest(String),
+    /// `INTERNAL_ERROR` from the node
+    Internal(String),
+    /// Other errors, usually about the node's state, like
+    /// `UNAVAILABLE_SHARD` or `NOT_SYNCED_YET`
+    Other(String),
+}
+
+impl RpcError {
+    /// Whether another RPC might succeed where this one failed. Only errors
+    /// caused by the request would be the same on every RPC.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RpcError::Transport(_)
+            | RpcError::RateLimited
+            | RpcError::Timeout
+            | RpcError::UnknownBlock
+            | RpcError::Internal(_)
+            | RpcError::Other(_) => true,
+            RpcError::UnknownAccount(_)
+            | RpcError::NoContractCode(_)
+            | RpcError::ContractExecution(_)
+            | RpcError::InvalidResult(_)
+            | RpcError::InvalidRequest(_) => false,
+        }
+    }
+
+    fn from_response(error: RpcErrorResponse) -> Self {
+        let info_str = |cause: &RpcErrorCause, field: &str| {
+            cause
+                .info
+                .get(field)
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string())
+        };
+        let details = error
+            .data
+            .as_ref()
+            .map(|data| {
+                data.as_str()
+                    .map(|s| s.to_string())
+                    .unwrap_or(data.to_string())
+            })
+            .unwrap_or(error.message);
+        match (error.name.as_deref(), error.cause) {
+            (_, Some(cause)) => match cause.name.as_str() {
+                "UNKNOWN_ACCOUNT" => RpcError::UnknownAccount(
+                    info_str(&cause, "requested_account_id").unwrap_or(details),
+                ),
+                "UNKNOWN_BLOCK" => RpcError::UnknownBlock,
+                "TIMEOUT_ERROR" => RpcError::Timeout,
+                "NO_CONTRACT_CODE" => RpcError::NoContractCode(
+                    info_str(&cause, "contract_account_id").unwrap_or(details),
+                ),
+                "CONTRACT_EXECUTION_ERROR" => {
+                    RpcError::ContractExecution(info_str(&cause, "vm_error").unwrap_or(details))
+                }
+                "INTERNAL_ERROR" => RpcError::Internal(details),
+                "PARSE_ERROR" => RpcError::InvalidRequest(details),
+                name => RpcError::Other(format!("{name}: {details}")),
+            },
+            (Some("TIMEOUT_ERROR"), None) => RpcError::Timeout,
+            (Some("INTERNAL_ERROR"), None) => RpcError::Internal(details),
+            (name, None) => RpcError::Other(format!("{}: {details}", name.unwrap_or("UNKNOWN"))),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Transport(err) => write!(f, "RPC request failed: {err}"),
+            RpcError::RateLimited => write!(f, "Rate limited by RPC"),
+            RpcError::Timeout => write!(f, "RPC timed out"),
+            RpcError::UnknownBlock => write!(f, "Block is not available on this RPC"),
+            RpcError::UnknownAccount(account_id) => {
+                write!(f, "Account {account_id} does not exist")
+            }
+            RpcError::NoContractCode(account_id) => {
+                write!(f, "Account {account_id} has no contract deployed")
+            }
+            RpcError::ContractExecution(err) => write!(f, "Contract execution failed: {err}"),
+            RpcError::InvalidResult(err) => write!(f, "Unexpected contract result: {err}"),
+            RpcError::InvalidRequest(err) => write!(f, "Invalid RPC request: {err}"),
+            RpcError::Internal(err) => write!(f, "RPC internal error: {err}"),
+            RpcError::Other(err) => write!(f, "RPC error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<reqwest::Error> for RpcError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) {
+            RpcError::RateLimited
+        } else {
+            RpcError::Transport(err.into())
+        }
+    }
 }
 
 lazy_static! {
@@ -55,26 +201,38 @@ pub fn get_reqwest_client() -> &'static reqwest::Client {
     &CLIENT
 }
 
-pub async fn rpc<I: Serialize, O: DeserializeOwned>(
-    data: I,
-) -> Result<RpcResponse<O>, anyhow::Error> {
+pub async fn rpc<I: Serialize, O: DeserializeOwned>(data: I) -> Result<O, RpcError> {
     try_rpc!(|rpc_url| {
-        Ok(get_reqwest_client()
+        let response = get_reqwest_client()
             .post(rpc_url)
             .json(&data)
             .send()
-            .await?
-            .json::<RpcResponse<O>>()
-            .await?)
+            .await?;
+        // Some RPCs return JSON-RPC errors with non-200 status codes
+        let status = response.status();
+        match response.json::<RpcResponse<O>>().await {
+            Ok(RpcResponse {
+                result: Some(result),
+                ..
+            }) => Ok(result),
+            Ok(RpcResponse {
+                error: Some(error), ..
+            }) => Err(RpcError::from_response(error)),
+            Ok(_) => Err(RpcError::Transport(anyhow::anyhow!(
+                "Response has neither result nor error"
+            ))),
+            Err(_) if status == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                Err(RpcError::RateLimited)
+            }
+            Err(err) => Err(err.into()),
+        }
     })
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AccountInfo {
-    #[serde(with = "dec_format")]
-    pub amount: u128,
-    #[serde(with = "dec_format")]
-    pub locked: u128,
+    pub amount: NearAmount,
+    pub locked: NearAmount,
     pub code_hash: String,
     pub storage_usage: u64,
     pub storage_paid_at: u64,
@@ -82,8 +240,64 @@ pub struct AccountInfo {
     pub block_hash: String,
 }
 
-pub async fn view_account_not_cached(account_id: &AccountId) -> Result<AccountInfo, anyhow::Error> {
-    let response = rpc::<_, AccountInfo>(serde_json::json!({
+/// 1 NEAR per 100 kB, used if the protocol config can't be loaded
+const STORAGE_PRICE_PER_BYTE: NearAmount = NearAmount::from_yocto(10_000_000_000_000_000_000);
+
+/// Liquid NEAR of an account and how much of it can be spent
+pub struct SpendableBalance {
+    pub balance: NearAmount,
+    /// Locked for the account's storage
+    pub storage_cost: NearAmount,
+    /// Left for gas, from the config
+    pub gas_reserve: NearAmount,
+    pub spendable: NearAmount,
+}
+
+impl SpendableBalance {
+    /// `X is locked for account storage and Y is left for gas`
+    pub fn describe_reserved(&self) -> String {
+        format!(
+            "{} is locked for account storage and {} is left for gas",
+            self.storage_cost, self.gas_reserve
+        )
+    }
+}
+
+impl AccountInfo {
+    /// NEAR locked for the account's storage at the given price
+    pub fn storage_cost(&self, price_per_byte: NearAmount) -> NearAmount {
+        NearAmount::from_yocto(
+            (self.storage_usage as u128).saturating_mul(price_per_byte.as_yocto()),
+        )
+    }
+}
+
+/// Liquid balance minus the cost of the account's storage and the gas
+/// reserve. The storage price is from the protocol config.
+pub async fn get_spendable_balance(account_id: &AccountId) -> Result<SpendableBalance, RpcError> {
+    let account = view_account_cached_30s(account_id.clone()).await?;
+    let storage_price = match get_protocol_config().await {
+        Ok(config) => config.runtime_config.storage_amount_per_byte,
+        Err(err) => {
+            log::warn!("Failed to get protocol config, using the default storage price: {err:?}");
+            STORAGE_PRICE_PER_BYTE
+        }
+    };
+    let storage_cost = account.storage_cost(storage_price);
+    let gas_reserve = CONFIG.gas_reserve();
+    Ok(SpendableBalance {
+        balance: account.amount,
+        storage_cost,
+        gas_reserve,
+        spendable: account
+            .amount
+            .saturating_sub(storage_cost)
+            .saturating_sub(gas_reserve),
+    })
+}
+
+pub async fn view_account_not_cached(account_id: &AccountId) -> Result<AccountInfo, RpcError> {
+    rpc::<_, AccountInfo>(serde_json::json!({
         "jsonrpc": "2.0",
         "id": "dontcare",
         "method": "query",
@@ -93,21 +307,33 @@ pub async fn view_account_not_cached(account_id: &AccountId) -> Result<AccountIn
             "account_id": account_id,
         }
     }))
-    .await?
-    .result;
-    Ok(response)
+    .await
 }
 
-#[cached(time = 30, result = true, size = 1000)]
-pub async fn view_account_cached_30s(account_id: AccountId) -> Result<AccountInfo, anyhow::Error> {
+#[cached(
+    ty = "cached::TimedSizedCache<AccountId, AccountInfo>",
+    create = "{ cached::TimedSizedCache::with_size_and_lifespan(1000, CONFIG.cache.rpc_ttl_secs) }",
+    result = true
+)]
+pub async fn view_account_cached_30s(account_id: AccountId) -> Result<AccountInfo, RpcError> {
     view_account_not_cached(&account_id).await
 }
 
 async fn _get_internal(uri: &str) -> Result<serde_json::Value, anyhow::Error> {
-    Ok(get_reqwest_client().get(uri).send().await?.json().await?)
+    Ok(get_reqwest_client()
+        .get(uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
 }
 
-#[cached(time = 30, result = true, size = 50)]
+#[cached(
+    ty = "cached::TimedSizedCache<String, serde_json::Value>",
+    create = "{ cached::TimedSizedCache::with_size_and_lifespan(50, CONFIG.cache.http_ttl_secs) }",
+    result = true
+)]
 async fn _get_cached_30s(uri: String) -> Result<serde_json::Value, anyhow::Error> {
     _get_internal(&uri).await
 }
@@ -117,6 +343,14 @@ pub async fn get_cached_30s<O: DeserializeOwned>(uri: &str) -> Result<O, anyhow:
     Ok(serde_json::from_value(res)?)
 }
 
+/// Older nodes return contract errors as a successful response with `error`
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RpcResponseCallFunction {
+    View(RpcResponseCallFunctionView),
+    Error { error: String },
+}
+
 #[derive(Deserialize, Debug)]
 struct RpcResponseCallFunctionView {
     #[serde(deserialize_with = "from_bytes")]
@@ -141,8 +375,8 @@ async fn _internal_view(
     contract_id: &str,
     method_name: &str,
     args: &str,
-) -> Result<serde_json::Value, anyhow::Error> {
-    let response = rpc::<_, RpcResponseCallFunctionView>(serde_json::json!({
+) -> Result<serde_json::Value, RpcError> {
+    let response = rpc::<_, RpcResponseCallFunction>(serde_json::json!({
         "jsonrpc": "2.0",
         "id": "dontcare",
         "method": "query",
@@ -154,17 +388,25 @@ async fn _internal_view(
             "args_base64": base64::prelude::BASE64_STANDARD.encode(args.as_bytes()),
         }
     }))
-    .await?
-    .result;
-    Ok(serde_json::from_str(&response.result)?)
+    .await?;
+    match response {
+        RpcResponseCallFunction::View(view) => {
+            serde_json::from_str(&view.result).map_err(|err| RpcError::InvalidResult(err.into()))
+        }
+        RpcResponseCallFunction::Error { error } => Err(RpcError::ContractExecution(error)),
+    }
 }
 
-#[cached(time = 30, result = true, size = 1000)]
+#[cached(
+    ty = "cached::TimedSizedCache<(String, String, String), serde_json::Value>",
+    create = "{ cached::TimedSizedCache::with_size_and_lifespan(1000, CONFIG.cache.rpc_ttl_secs) }",
+    result = true
+)]
 async fn _internal_view_cached_30s(
     contract_id: String,
     method_name: String,
     args: String,
-) -> Result<serde_json::Value, anyhow::Error> {
+) -> Result<serde_json::Value, RpcError> {
     _internal_view(&contract_id, &method_name, &args).await
 }
 
@@ -172,10 +414,195 @@ pub async fn view_cached_30s<I: Serialize, O: DeserializeOwned>(
     contract_id: impl AsRef<str>,
     method_name: impl AsRef<str>,
     args: I,
-) -> Result<O, anyhow::Error> {
+) -> Result<O, RpcError> {
     let contract_id = contract_id.as_ref().to_string();
     let method_name = method_name.as_ref().to_string();
-    let args = serde_json::to_string(&args)?;
-    let res = _internal_view_cached_30s(contract_id, method_name, args).await;
-    Ok(serde_json::from_value(res?)?)
+    let args = serde_json::to_string(&args).map_err(|err| {
+        RpcError::InvalidRequest(format!("Failed to serialize view call arguments: {err}"))
+    })?;
+    let res = _internal_view_cached_30s(contract_id, method_name, args).await?;
+    serde_json::from_value(res).map_err(|err| RpcError::InvalidResult(err.into()))
+}
+
+pub struct CacheStats {
+    pub name: &'static str,
+    pub hits: u64,
+    pub misses: u64,
+    pub size: usize,
+}
+
+pub async fn get_cache_stats() -> Vec<CacheStats> {
+    fn stats<K, V>(name: &'static str, cache: &impl Cached<K, V>) -> CacheStats {
+        CacheStats {
+            name,
+            hits: cache.cache_hits().unwrap_or_default(),
+            misses: cache.cache_misses().unwrap_or_default(),
+            size: cache.cache_size(),
+        }
+    }
+
+    vec![
+        stats("view_account", &*VIEW_ACCOUNT_CACHED_30S.lock().await),
+        stats("view_call", &*_INTERNAL_VIEW_CACHED_30S.lock().await),
+        stats("http_get", &*_GET_CACHED_30S.lock().await),
+        stats("ft_metadata", &*FT_METADATA_CACHED.lock().await),
+        stats("pool_fee", &*GET_POOL_FEE.lock().await),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Error responses as returned by nearcore
+    fn parse_error(response: serde_json::Value) -> RpcError {
+        let response: RpcResponse<serde_json::Value> = serde_json::from_value(response).unwrap();
+        RpcError::from_response(response.error.unwrap())
+    }
+
+    #[test]
+    fn request_errors_are_not_retried() {
+        let err = parse_error(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "dontcare",
+            "error": {
+                "name": "HANDLER_ERROR",
+                "cause": {
+                    "name": "UNKNOWN_ACCOUNT",
+                    "info": {
+                        "requested_account_id": "nope.near",
+                        "block_height": 140000000,
+                        "block_hash": "5Xz1YgM8r5YxJXeuEX8VcCqbL2RVvBpuNmtWbrv1Sh2Y",
+                    },
+                },
+                "code": -32000,
+                "message": "Server error",
+                "data": "account nope.near does not exist while viewing",
+            },
+        }));
+        assert!(matches!(&err, RpcError::UnknownAccount(account_id) if account_id == "nope.near"));
+        assert!(!err.is_retryable());
+
+        let err = parse_error(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "dontcare",
+            "error": {
+                "name": "HANDLER_ERROR",
+                "cause": {
+                    "name": "NO_CONTRACT_CODE",
+                    "info": {
+                        "contract_account_id": "alice.near",
+                        "block_height": 140000000,
+                        "block_hash": "5Xz1YgM8r5YxJXeuEX8VcCqbL2RVvBpuNmtWbrv1Sh2Y",
+                    },
+                },
+                "code": -32000,
+                "message": "Server error",
+                "data": "Contract code for contract ID #alice.near has never been observed on the node",
+            },
+        }));
+        assert!(matches!(&err, RpcError::NoContractCode(account_id) if account_id == "alice.near"));
+        assert!(!err.is_retryable());
+
+        let err = parse_error(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "dontcare",
+            "error": {
+                "name": "HANDLER_ERROR",
+                "cause": {
+                    "name": "CONTRACT_EXECUTION_ERROR",
+                    "info": {
+                        "vm_error": "wasm execution failed with error: MethodResolveError(MethodNotFound)",
+                        "block_height": 140000000,
+                        "block_hash": "5Xz1YgM8r5YxJXeuEX8VcCqbL2RVvBpuNmtWbrv1Sh2Y",
+                    },
+                },
+                "code": -32000,
+                "message": "Server error",
+                "data": "wasm execution failed with error: MethodResolveError(MethodNotFound)",
+            },
+        }));
+        assert!(
+            matches!(&err, RpcError::ContractExecution(error) if error.contains("MethodNotFound"))
+        );
+        assert!(!err.is_retryable());
+
+        let err = parse_error(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "dontcare",
+            "error": {
+                "name": "REQUEST_VALIDATION_ERROR",
+                "cause": {
+                    "name": "PARSE_ERROR",
+                    "info": {
+                        "error_message": "Failed parsing args: missing field `request_type`",
+                    },
+                },
+                "code": -32700,
+                "message": "Parse error",
+                "data": "Failed parsing args: missing field `request_type`",
+            },
+        }));
+        assert!(matches!(err, RpcError::InvalidRequest(_)));
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn node_errors_are_retried() {
+        let handler_error = |cause: serde_json::Value| {
+            parse_error(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": "dontcare",
+                "error": {
+                    "name": "HANDLER_ERROR",
+                    "cause": cause,
+                    "code": -32000,
+                    "message": "Server error",
+                    "data": "",
+                },
+            }))
+        };
+        for cause in [
+            serde_json::json!({ "name": "UNAVAILABLE_SHARD", "info": { "requested_shard_id": 0 } }),
+            serde_json::json!({ "name": "NO_SYNCED_BLOCKS" }),
+            serde_json::json!({ "name": "NOT_SYNCED_YET" }),
+            serde_json::json!({
+                "name": "GARBAGE_COLLECTED_BLOCK",
+                "info": {
+                    "block_height": 100,
+                    "block_hash": "5Xz1YgM8r5YxJXeuEX8VcCqbL2RVvBpuNmtWbrv1Sh2Y",
+                },
+            }),
+        ] {
+            let err = handler_error(cause);
+            assert!(matches!(err, RpcError::Other(_)), "{err:?}");
+            assert!(err.is_retryable());
+        }
+
+        assert!(matches!(
+            handler_error(serde_json::json!({ "name": "UNKNOWN_BLOCK", "info": {} })),
+            RpcError::UnknownBlock
+        ));
+        assert!(matches!(
+            handler_error(serde_json::json!({ "name": "TIMEOUT_ERROR" })),
+            RpcError::Timeout
+        ));
+
+        let err = parse_error(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "dontcare",
+            "error": {
+                "name": "INTERNAL_ERROR",
+                "cause": {
+                    "name": "INTERNAL_ERROR",
+                    "info": { "error_message": "Node is shutting down" },
+                },
+                "code": -32000,
+                "message": "Server error",
+                "data": "Node is shutting down",
+            },
+        }));
+        assert!(matches!(&err, RpcError::Internal(details) if details == "Node is shutting down"));
+        assert!(err.is_retryable());
+    }
 }
@@ -0,0 +1,197 @@
+use near_primitives::serialize::dec_format;
+use near_primitives::types::{AccountId, Gas};
+use serde::Serialize;
+use warp::Reply;
+
+use super::{amount::NearAmount, response::ResponseFormat};
+
+/// 50 TGas, enough for all staking pool methods
+pub const DEFAULT_GAS: Gas = 50_000_000_000_000;
+
+/// A list of transactions in the format that `generate-transaction` tool accepts
+#[derive(Debug, Serialize, Default)]
+pub struct TransactionBatch {
+    pub transactions: Vec<Transaction>,
+}
+
+/// What endpoints that create transactions return. In text responses,
+/// `message` follows the transactions, or replaces them if there are none.
+#[derive(Debug, Serialize)]
+pub struct TransactionResponse {
+    #[serde(flatten)]
+    pub batch: TransactionBatch,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    pub receiver_id: AccountId,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "params")]
+pub enum Action {
+    FunctionCall(FunctionCallAction),
+    Transfer(TransferAction),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCallAction {
+    pub method_name: String,
+    pub args: serde_json::Value,
+    #[serde(with = "dec_format")]
+    pub gas: Gas,
+    pub deposit: NearAmount,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferAction {
+    pub deposit: NearAmount,
+}
+
+impl FunctionCallAction {
+    pub fn new(
+        method_name: impl Into<String>,
+        args: serde_json::Value,
+        deposit: NearAmount,
+    ) -> Self {
+        Self {
+            method_name: method_name.into(),
+            args,
+            gas: DEFAULT_GAS,
+            deposit,
+        }
+    }
+
+    pub fn with_gas(self, gas: Gas) -> Self {
+        Self { gas, ..self }
+    }
+
+    /// Registers `account_id` on a token contract
+    pub fn storage_deposit(account_id: &AccountId, deposit: NearAmount) -> Self {
+        Self::new(
+            "storage_deposit",
+            serde_json::json!({
+                "account_id": account_id,
+                "registration_only": true,
+            }),
+            deposit,
+        )
+    }
+}
+
+impl Transaction {
+    pub fn new(receiver_id: AccountId) -> Self {
+        Self {
+            receiver_id,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn function_call(mut self, action: FunctionCallAction) -> Self {
+        self.actions.push(Action::FunctionCall(action));
+        self
+    }
+
+    pub fn transfer(mut self, deposit: NearAmount) -> Self {
+        self.actions
+            .push(Action::Transfer(TransferAction { deposit }));
+        self
+    }
+}
+
+impl TransactionBatch {
+    pub fn single(transaction: Transaction) -> Self {
+        Self {
+            transactions: vec![transaction],
+        }
+    }
+
+    pub fn push(&mut self, transaction: Transaction) {
+        self.transactions.push(transaction);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Formats the batch for the LLM, followed by additional instructions
+    pub fn to_llm_response(&self, instructions: &str) -> String {
+        format!(
+            "Use `generate-transaction` tool with these exact arguments:\n{}\n\n{instructions}",
+            serde_json::to_string_pretty(self).expect("Failed to serialize transactions"),
+        )
+        .trim_end()
+        .to_string()
+    }
+}
+
+impl TransactionResponse {
+    pub fn new(batch: TransactionBatch, message: impl Into<String>) -> Self {
+        Self {
+            batch,
+            message: message.into(),
+        }
+    }
+
+    /// A response without transactions, e.g. when there's nothing to do
+    pub fn message(message: impl Into<String>) -> Self {
+        Self::new(TransactionBatch::default(), message)
+    }
+
+    pub fn into_reply(self, format: ResponseFormat) -> warp::reply::Response {
+        match format {
+            ResponseFormat::Json => warp::reply::json(&self).into_response(),
+            ResponseFormat::Text if self.batch.is_empty() => self.message.into_response(),
+            ResponseFormat::Text => self.batch.to_llm_response(&self.message).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_in_generate_transaction_format() {
+        let mut batch = TransactionBatch::single(
+            Transaction::new("pool.near".parse().unwrap()).function_call(FunctionCallAction::new(
+                "deposit_and_stake",
+                serde_json::json!({}),
+                NearAmount::from_yocto(10u128.pow(24)),
+            )),
+        );
+        batch.push(
+            Transaction::new("bob.near".parse().unwrap()).transfer(NearAmount::from_yocto(5)),
+        );
+        assert_eq!(
+            serde_json::to_value(&batch).unwrap(),
+            serde_json::json!({
+                "transactions": [
+                    {
+                        "receiverId": "pool.near",
+                        "actions": [{
+                            "type": "FunctionCall",
+                            "params": {
+                                "methodName": "deposit_and_stake",
+                                "args": {},
+                                "gas": "50000000000000",
+                                "deposit": "1000000000000000000000000",
+                            },
+                        }],
+                    },
+                    {
+                        "receiverId": "bob.near",
+                        "actions": [{
+                            "type": "Transfer",
+                            "params": { "deposit": "5" },
+                        }],
+                    },
+                ],
+            })
+        );
+    }
+}
@@ -0,0 +1,433 @@
+//! Validators, epochs, and staking rewards. Loading data from RPC is separate
+//! from the reward math, which only depends on the data passed to it.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bigdecimal::{BigDecimal, One, Zero};
+use cached::proc_macro::cached;
+use near_primitives::{serialize::dec_format, types::AccountId};
+use serde::Deserialize;
+
+use crate::config::CONFIG;
+
+use super::{
+    amount::NearAmount,
+    rpc::{rpc, view_cached_30s, RpcError},
+};
+
+/// Keeps the list of staking pools that lockup contracts are allowed to use
+const STAKING_POOL_WHITELIST: &str = "lockup-whitelist.near";
+/// Staking pools let unstaked NEAR be withdrawn this many epochs after
+/// unstaking
+const UNSTAKE_EPOCHS: u64 = 4;
+/// Used if the block time can't be measured, roughly mainnet's
+const DEFAULT_BLOCK_TIME: Duration = Duration::from_secs(1);
+/// Pools rarely change their fee, and `/validators` needs the fee of every
+/// pool, so fees are kept much longer than other view results
+const POOL_FEE_TTL_SECS: u64 = 60 * 60;
+
+/// Response of the `validators` RPC method for the current epoch
+#[derive(Debug, Clone, Deserialize)]
+pub struct EpochValidatorInfo {
+    pub epoch_height: u64,
+    pub epoch_start_height: u64,
+    pub current_validators: Vec<CurrentValidator>,
+    pub next_validators: Vec<NextValidator>,
+    pub current_proposals: Vec<ValidatorProposal>,
+    pub prev_epoch_kickout: Vec<ValidatorKickout>,
+}
+
+impl EpochValidatorInfo {
+    /// Validators of the current and the next epoch, and proposals for the
+    /// next epoch. The same account can be returned several times.
+    pub fn validator_ids(&self) -> impl Iterator<Item = &AccountId> {
+        self.current_validators
+            .iter()
+            .map(|validator| &validator.account_id)
+            .chain(self.next_validators.iter().map(|v| &v.account_id))
+            .chain(self.current_proposals.iter().map(|v| &v.account_id))
+    }
+
+    pub fn is_validator(&self, account_id: &AccountId) -> bool {
+        self.validator_ids()
+            .any(|validator| validator == account_id)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CurrentValidator {
+    pub account_id: AccountId,
+    pub stake: NearAmount,
+    pub num_produced_blocks: u64,
+    pub num_expected_blocks: u64,
+    pub num_produced_chunks: u64,
+    pub num_expected_chunks: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NextValidator {
+    pub account_id: AccountId,
+    pub stake: NearAmount,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ValidatorProposal {
+    pub account_id: AccountId,
+    pub stake: NearAmount,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ValidatorKickout {
+    pub account_id: AccountId,
+    /// A string like `"Unstaked"` or an object like
+    /// `{"NotEnoughBlocks": {"produced": 0, "expected": 10}}`
+    pub reason: serde_json::Value,
+}
+
+impl ValidatorKickout {
+    /// `NotEnoughBlocks`, `Unstaked`, etc.
+    pub fn reason_name(&self) -> String {
+        match &self.reason {
+            serde_json::Value::String(reason) => reason.clone(),
+            serde_json::Value::Object(reason) => reason.keys().next().cloned().unwrap_or_default(),
+            reason => reason.to_string(),
+        }
+    }
+}
+
+/// A fraction, serialized by nearcore as `[numerator, denominator]`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Rational(pub u64, pub u64);
+
+impl Rational {
+    pub fn to_decimal(self) -> BigDecimal {
+        if self.1 == 0 {
+            return BigDecimal::zero();
+        }
+        BigDecimal::from(self.0) / BigDecimal::from(self.1)
+    }
+}
+
+/// The parts of `EXPERIMENTAL_protocol_config` needed for rewards and
+/// storage
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProtocolConfig {
+    /// In blocks
+    pub epoch_length: u64,
+    pub max_inflation_rate: Rational,
+    /// Part of the inflation that goes to the treasury instead of validators
+    pub protocol_reward_rate: Rational,
+    /// Validators below this uptime get no rewards
+    pub online_min_threshold: Rational,
+    /// Validators above this uptime get full rewards
+    pub online_max_threshold: Rational,
+    pub runtime_config: RuntimeConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuntimeConfig {
+    pub storage_amount_per_byte: NearAmount,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BlockView {
+    header: BlockHeaderView,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockHeaderView {
+    pub height: u64,
+    #[serde(with = "dec_format")]
+    pub timestamp_nanosec: u64,
+    pub total_supply: NearAmount,
+}
+
+/// Where the chain is in the current epoch, to estimate when the next ones
+/// start
+#[derive(Debug, Clone)]
+pub struct EpochProgress {
+    pub epoch_height: u64,
+    pub epoch_start_height: u64,
+    pub epoch_length: u64,
+    pub block_height: u64,
+    pub block_timestamp: SystemTime,
+    /// Average in the current epoch
+    pub block_time: Duration,
+}
+
+impl EpochProgress {
+    /// Estimated start of an epoch, the current time for past epochs
+    pub fn epoch_start_time(&self, epoch_height: u64) -> SystemTime {
+        let start_height = self.epoch_start_height
+            + epoch_height.saturating_sub(self.epoch_height) * self.epoch_length;
+        let blocks_left = start_height.saturating_sub(self.block_height);
+        self.block_timestamp + self.block_time * blocks_left.try_into().unwrap_or(u32::MAX)
+    }
+
+    /// Estimated epoch of a block in the current or a past epoch
+    pub fn epoch_of_height(&self, height: u64) -> u64 {
+        if self.epoch_length == 0 || height >= self.epoch_start_height {
+            return self.epoch_height;
+        }
+        let epochs_ago = (self.epoch_start_height - height).div_ceil(self.epoch_length);
+        self.epoch_height.saturating_sub(epochs_ago)
+    }
+
+    /// Epoch when NEAR unstaked at `unstake_height` can be withdrawn. If the
+    /// unstake height is unknown, it's the latest possible epoch, as if it
+    /// was unstaked just now. Never later than that, and never earlier than
+    /// the next epoch, since the NEAR isn't withdrawable yet.
+    pub fn unstake_available_epoch(&self, unstake_height: Option<u64>) -> u64 {
+        let latest = self.epoch_height + UNSTAKE_EPOCHS;
+        match unstake_height {
+            Some(height) => {
+                (self.epoch_of_height(height) + UNSTAKE_EPOCHS).clamp(self.epoch_height + 1, latest)
+            }
+            None => latest,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RewardFeeFraction {
+    numerator: u32,
+    denominator: u32,
+}
+
+#[cached(
+    ty = "cached::TimedCache<(), EpochValidatorInfo>",
+    create = "{ cached::TimedCache::with_lifespan(CONFIG.cache.rpc_ttl_secs) }",
+    result = true
+)]
+pub async fn get_epoch_validators() -> Result<EpochValidatorInfo, RpcError> {
+    rpc(serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "dontcare",
+        "method": "validators",
+        "params": [null],
+    }))
+    .await
+}
+
+#[cached(
+    ty = "cached::TimedCache<(), ProtocolConfig>",
+    create = "{ cached::TimedCache::with_lifespan(CONFIG.cache.rpc_ttl_secs) }",
+    result = true
+)]
+pub async fn get_protocol_config() -> Result<ProtocolConfig, RpcError> {
+    rpc(serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "dontcare",
+        "method": "EXPERIMENTAL_protocol_config",
+        "params": { "finality": "final" },
+    }))
+    .await
+}
+
+#[cached(
+    ty = "cached::TimedCache<(), BlockHeaderView>",
+    create = "{ cached::TimedCache::with_lifespan(CONFIG.cache.rpc_ttl_secs) }",
+    result = true
+)]
+pub async fn get_final_block() -> Result<BlockHeaderView, RpcError> {
+    let block: BlockView = rpc(serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "dontcare",
+        "method": "block",
+        "params": { "finality": "final" },
+    }))
+    .await?;
+    Ok(block.header)
+}
+
+/// Blocks don't change, so they're cached without a TTL
+#[cached(
+    ty = "cached::SizedCache<u64, BlockHeaderView>",
+    create = "{ cached::SizedCache::with_size(100) }",
+    result = true
+)]
+async fn get_block_at(height: u64) -> Result<BlockHeaderView, RpcError> {
+    let block: BlockView = rpc(serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "dontcare",
+        "method": "block",
+        "params": { "block_id": height },
+    }))
+    .await?;
+    Ok(block.header)
+}
+
+pub async fn get_total_supply() -> Result<NearAmount, RpcError> {
+    Ok(get_final_block().await?.total_supply)
+}
+
+pub async fn get_epoch_progress() -> Result<EpochProgress, RpcError> {
+    let epoch = get_epoch_validators().await?;
+    let config = get_protocol_config().await?;
+    let block = get_final_block().await?;
+    // Measured from the start of the epoch. The block at that height may be
+    // missing, or already garbage collected.
+    let block_time = match get_block_at(epoch.epoch_start_height).await {
+        Ok(start) if block.height > start.height => Duration::from_nanos(
+            block
+                .timestamp_nanosec
+                .saturating_sub(start.timestamp_nanosec)
+                / (block.height - start.height),
+        ),
+        Ok(_) => DEFAULT_BLOCK_TIME,
+        Err(err) => {
+            log::warn!("Failed to get the first block of the epoch: {err:?}");
+            DEFAULT_BLOCK_TIME
+        }
+    };
+    Ok(EpochProgress {
+        epoch_height: epoch.epoch_height,
+        epoch_start_height: epoch.epoch_start_height,
+        epoch_length: config.epoch_length,
+        block_height: block.height,
+        block_timestamp: UNIX_EPOCH + Duration::from_nanos(block.timestamp_nanosec),
+        block_time,
+    })
+}
+
+/// Commission of a staking pool, from 0 to 1
+#[cached(
+    ty = "cached::TimedSizedCache<AccountId, BigDecimal>",
+    create = "{ cached::TimedSizedCache::with_size_and_lifespan(1000, POOL_FEE_TTL_SECS) }",
+    convert = r#"{ pool_id.clone() }"#,
+    result = true
+)]
+pub async fn get_pool_fee(pool_id: &AccountId) -> Result<BigDecimal, RpcError> {
+    let fee: RewardFeeFraction =
+        view_cached_30s(pool_id, "get_reward_fee_fraction", serde_json::json!({})).await?;
+    Ok(Rational(fee.numerator.into(), fee.denominator.into()).to_decimal())
+}
+
+/// Total NEAR staked in a pool. Fails if the account is not a staking pool.
+pub async fn get_pool_total_stake(pool_id: &AccountId) -> Result<NearAmount, RpcError> {
+    view_cached_30s(pool_id, "get_total_staked_balance", serde_json::json!({})).await
+}
+
+/// Whether the pool is whitelisted for lockup contracts, which is the closest
+/// thing NEAR has to a list of genuine staking pools
+pub async fn is_whitelisted_pool(pool_id: &AccountId) -> Result<bool, RpcError> {
+    view_cached_30s(
+        STAKING_POOL_WHITELIST,
+        "is_whitelisted",
+        serde_json::json!({ "staking_pool_account_id": pool_id }),
+    )
+    .await
+}
+
+/// Average of produced / expected blocks and chunks, from 0 to 1. `None` if
+/// the validator wasn't expected to produce anything yet.
+pub fn uptime(validator: &CurrentValidator) -> Option<BigDecimal> {
+    let ratios = [
+        (validator.num_produced_blocks, validator.num_expected_blocks),
+        (validator.num_produced_chunks, validator.num_expected_chunks),
+    ]
+    .into_iter()
+    .filter(|(_, expected)| *expected > 0)
+    .map(|(produced, expected)| Rational(produced, expected).to_decimal())
+    .collect::<Vec<_>>();
+    if ratios.is_empty() {
+        return None;
+    }
+    let count = BigDecimal::from(ratios.len() as u64);
+    Some(ratios.into_iter().sum::<BigDecimal>() / count)
+}
+
+/// Part of the full reward a validator gets for its uptime, from 0 to 1
+pub fn reward_fraction(uptime: &BigDecimal, config: &ProtocolConfig) -> BigDecimal {
+    let min = config.online_min_threshold.to_decimal();
+    let max = config.online_max_threshold.to_decimal();
+    if uptime <= &min {
+        BigDecimal::zero()
+    } else if uptime >= &max {
+        BigDecimal::one()
+    } else {
+        (uptime - &min) / (max - &min)
+    }
+}
+
+/// Yearly reward of staked NEAR for validators with full uptime, before
+/// pool fees, from 0 to 1
+pub fn network_apy(
+    config: &ProtocolConfig,
+    total_supply: NearAmount,
+    total_stake: NearAmount,
+) -> BigDecimal {
+    if total_stake.is_zero() {
+        return BigDecimal::zero();
+    }
+    BigDecimal::from(total_supply.as_yocto())
+        * config.max_inflation_rate.to_decimal()
+        * (BigDecimal::one() - config.protocol_reward_rate.to_decimal())
+        / BigDecimal::from(total_stake.as_yocto())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Epoch 100 started at block 10000, and it's now block 10400
+    fn progress() -> EpochProgress {
+        EpochProgress {
+            epoch_height: 100,
+            epoch_start_height: 10_000,
+            epoch_length: 1000,
+            block_height: 10_400,
+            block_timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            block_time: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn epoch_of_height() {
+        let progress = progress();
+        assert_eq!(progress.epoch_of_height(10_400), 100);
+        assert_eq!(progress.epoch_of_height(10_000), 100);
+        // Future heights are in the current epoch
+        assert_eq!(progress.epoch_of_height(20_000), 100);
+        assert_eq!(progress.epoch_of_height(9_999), 99);
+        assert_eq!(progress.epoch_of_height(9_000), 99);
+        assert_eq!(progress.epoch_of_height(8_999), 98);
+        assert_eq!(progress.epoch_of_height(0), 90);
+
+        let no_length = EpochProgress {
+            epoch_length: 0,
+            ..progress
+        };
+        assert_eq!(no_length.epoch_of_height(0), 100);
+    }
+
+    #[test]
+    fn unstake_available_epoch() {
+        let progress = progress();
+        assert_eq!(progress.unstake_available_epoch(None), 104);
+        assert_eq!(progress.unstake_available_epoch(Some(10_400)), 104);
+        assert_eq!(progress.unstake_available_epoch(Some(9_500)), 103);
+        assert_eq!(progress.unstake_available_epoch(Some(7_500)), 101);
+        // Long ago, but not withdrawable yet, so at least the next epoch
+        assert_eq!(progress.unstake_available_epoch(Some(0)), 101);
+        assert_eq!(progress.unstake_available_epoch(Some(20_000)), 104);
+    }
+
+    #[test]
+    fn epoch_start_time() {
+        let progress = progress();
+        let now = progress.block_timestamp;
+        let seconds = Duration::from_secs;
+        // Past epochs and the current one already started
+        assert_eq!(progress.epoch_start_time(99), now);
+        assert_eq!(progress.epoch_start_time(100), now);
+        assert_eq!(progress.epoch_start_time(101), now + seconds(600));
+        assert_eq!(progress.epoch_start_time(104), now + seconds(3600));
+
+        let slow = EpochProgress {
+            block_time: Duration::from_millis(1500),
+            ..progress
+        };
+        assert_eq!(slow.epoch_start_time(101), now + seconds(900));
+    }
+}